@@ -0,0 +1,286 @@
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::ExitCode;
+
+use clap::{
+    Parser,
+    Subcommand,
+};
+use syxpack::{
+    MidiChannel,
+    Ranged,
+};
+
+use sevenate::dx7::ALGORITHM_DIAGRAMS;
+use sevenate::dx7::cartridge::{
+    Cartridge,
+    VOICE_COUNT,
+};
+use sevenate::dx7::sysex::{
+    self,
+    Entry,
+    Message,
+    Payload,
+};
+use sevenate::dx7::voice::{
+    Voice,
+    VoiceName,
+    VOICE_NAME_LENGTH,
+};
+
+/// Inspect and convert Yamaha DX7 voice and cartridge files.
+///
+/// File formats are chosen by extension: .syx for System Exclusive
+/// bulk dumps, .txt for the text patch format and .json for JSON.
+#[derive(Parser)]
+#[command(name = "sevenate", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the voices in a file.
+    List {
+        file: PathBuf,
+    },
+
+    /// Print one voice in detail, with its algorithm diagram.
+    Show {
+        file: PathBuf,
+
+        /// Voice number in the cartridge (1...32).
+        #[arg(short, long, default_value_t = 1)]
+        voice: usize,
+    },
+
+    /// Extract one voice from a cartridge as a single voice file.
+    Extract {
+        file: PathBuf,
+
+        /// Voice number in the cartridge (1...32).
+        #[arg(short, long)]
+        voice: usize,
+
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// MIDI channel of the bulk dump (1...16).
+        #[arg(short, long, default_value_t = 1)]
+        channel: i32,
+    },
+
+    /// Assemble a cartridge from single voice files. Missing voices
+    /// are filled with the initial voice.
+    Assemble {
+        files: Vec<PathBuf>,
+
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// MIDI channel of the bulk dump (1...16).
+        #[arg(short, long, default_value_t = 1)]
+        channel: i32,
+    },
+
+    /// Check the byte counts and checksums of System Exclusive files.
+    Check {
+        files: Vec<PathBuf>,
+    },
+
+    /// Convert a voice or a cartridge to another format.
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+
+        /// MIDI channel of the bulk dump (1...16).
+        #[arg(short, long, default_value_t = 1)]
+        channel: i32,
+    },
+}
+
+// The contents of a voice or cartridge file.
+enum Patch {
+    Voice(Box<Voice>),
+    Cartridge(Cartridge),
+}
+
+impl Patch {
+    fn voices(&self) -> Vec<&Voice> {
+        match self {
+            Patch::Voice(voice) => vec![voice],
+            Patch::Cartridge(cartridge) => cartridge.voices.iter().collect(),
+        }
+    }
+
+    // Checks that the patch can be written as a bulk dump.
+    fn validate(&self) -> Result<(), String> {
+        if let Patch::Cartridge(cartridge) = self
+            && cartridge.voices.len() != VOICE_COUNT {
+            return Err(format!("cartridge has {} voices, expected {}", cartridge.voices.len(), VOICE_COUNT));
+        }
+        for (i, voice) in self.voices().iter().enumerate() {
+            if !VoiceName::is_valid(&voice.name.value()) {
+                return Err(format!("voice {} has a bad name \"{}\", expected at most {} ASCII characters",
+                    i + 1, voice.name, VOICE_NAME_LENGTH));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase()
+}
+
+fn channel(value: i32) -> Result<MidiChannel, String> {
+    if MidiChannel::contains(value) {
+        Ok(MidiChannel::new(value))
+    } else {
+        Err(format!("bad MIDI channel {}, expected 1...16", value))
+    }
+}
+
+fn read_patch(path: &Path) -> Result<Patch, String> {
+    let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+    match extension(path).as_str() {
+        "syx" => {
+            let data = fs::read(path).map_err(|e| error(&e))?;
+            for entry in sysex::scan(&data) {
+                match entry {
+                    Entry::Message { message, .. } => match message.payload {
+                        Payload::Voice(voice) => return Ok(Patch::Voice(voice)),
+                        Payload::Cartridge(cartridge) => return Ok(Patch::Cartridge(cartridge)),
+                        _ => {},
+                    },
+                    Entry::Skipped { offset, reason: sysex::SkipReason::Invalid(e), .. } => {
+                        return Err(error(&format!("at offset {}: {}", offset, e)));
+                    },
+                    Entry::Skipped { .. } => {},
+                }
+            }
+            Err(error(&"no voice or cartridge bulk dump found"))
+        },
+        "txt" => {
+            let text = fs::read_to_string(path).map_err(|e| error(&e))?;
+            if text.lines().any(|line| line.trim() == sevenate::dx7::text::VOICE_SEPARATOR) {
+                Cartridge::from_text(&text).map(Patch::Cartridge).map_err(|e| error(&e))
+            } else {
+                Voice::from_text(&text).map(|v| Patch::Voice(Box::new(v))).map_err(|e| error(&e))
+            }
+        },
+        "json" => {
+            let text = fs::read_to_string(path).map_err(|e| error(&e))?;
+            let value: serde_json::Value = serde_json::from_str(&text).map_err(|e| error(&e))?;
+            let patch = if value.get("voices").is_some() {
+                serde_json::from_value(value).map(Patch::Cartridge).map_err(|e| error(&e))?
+            } else {
+                serde_json::from_value(value).map(Patch::Voice).map_err(|e| error(&e))?
+            };
+            patch.validate().map_err(|e| error(&e))?;
+            Ok(patch)
+        },
+        other => Err(error(&format!("unknown file type \"{}\", expected syx, txt or json", other))),
+    }
+}
+
+fn write_patch(path: &Path, patch: Patch, channel: MidiChannel) -> Result<(), String> {
+    let data = match extension(path).as_str() {
+        "syx" => match patch {
+            Patch::Voice(voice) => Message::voice(channel, *voice).to_bytes(),
+            Patch::Cartridge(cartridge) => Message::cartridge(channel, cartridge).to_bytes(),
+        },
+        "txt" => match patch {
+            Patch::Voice(voice) => voice.to_text().into_bytes(),
+            Patch::Cartridge(cartridge) => cartridge.to_text().into_bytes(),
+        },
+        "json" => match patch {
+            Patch::Voice(voice) => serde_json::to_vec_pretty(&voice),
+            Patch::Cartridge(cartridge) => serde_json::to_vec_pretty(&cartridge),
+        }.map_err(|e| e.to_string())?,
+        other => return Err(format!("{}: unknown file type \"{}\", expected syx, txt or json",
+            path.display(), other)),
+    };
+    fs::write(path, data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn select_voice(patch: &Patch, number: usize) -> Result<Voice, String> {
+    let voices = patch.voices();
+    if number < 1 || number > voices.len() {
+        return Err(format!("bad voice number {}, expected 1...{}", number, voices.len()));
+    }
+    Ok(voices[number - 1].clone())
+}
+
+fn run(command: Command) -> Result<bool, String> {
+    match command {
+        Command::List { file } => {
+            for (i, voice) in read_patch(&file)?.voices().iter().enumerate() {
+                println!("{:2}  {}  ALG {:2}", i + 1, voice.name, voice.alg);
+            }
+        },
+        Command::Show { file, voice } => {
+            let voice = select_voice(&read_patch(&file)?, voice)?;
+            println!("{}", voice);
+            println!("LFO: {}", voice.lfo_description());
+            println!("\nAlgorithm {}:{}", voice.alg, ALGORITHM_DIAGRAMS[voice.alg.value() as usize - 1]);
+        },
+        Command::Extract { file, voice, output, channel: c } => {
+            let voice = select_voice(&read_patch(&file)?, voice)?;
+            write_patch(&output, Patch::Voice(Box::new(voice)), channel(c)?)?;
+        },
+        Command::Assemble { files, output, channel: c } => {
+            if files.len() > VOICE_COUNT {
+                return Err(format!("too many voices, a cartridge has {}", VOICE_COUNT));
+            }
+            let mut cartridge = Cartridge::default();
+            for (i, file) in files.iter().enumerate() {
+                match read_patch(file)? {
+                    Patch::Voice(voice) => cartridge.voices[i] = *voice,
+                    Patch::Cartridge(_) => return Err(format!("{}: expected a single voice", file.display())),
+                }
+            }
+            write_patch(&output, Patch::Cartridge(cartridge), channel(c)?)?;
+        },
+        Command::Check { files } => {
+            let mut valid = true;
+            for file in files {
+                let data = fs::read(&file).map_err(|e| format!("{}: {}", file.display(), e))?;
+                for entry in sysex::scan(&data) {
+                    match entry {
+                        Entry::Message { offset, message } => {
+                            println!("{}: {}: OK, {}", file.display(), offset, message.header);
+                        },
+                        Entry::Skipped { offset, reason, .. } => {
+                            if let sysex::SkipReason::Invalid(_) = reason {
+                                valid = false;
+                            }
+                            println!("{}: {}: {}", file.display(), offset, reason);
+                        },
+                    }
+                }
+            }
+            return Ok(valid);
+        },
+        Command::Convert { input, output, channel: c } => {
+            write_patch(&output, read_patch(&input)?, channel(c)?)?;
+        },
+    }
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli.command) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        },
+    }
+}
@@ -0,0 +1,157 @@
+use syxpack::Ranged;
+
+use crate::dx7::Algorithm;
+use crate::dx7::voice::OPERATOR_COUNT;
+
+/// Routing of the operators in an algorithm. Operators are
+/// identified by their numbers 1...6.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Topology {
+    /// Operators whose output goes to the audio output.
+    pub carriers: Vec<usize>,
+
+    /// Modulation edges as (modulator, modulated operator).
+    pub edges: Vec<(usize, usize)>,
+
+    /// Feedback as (from, to): the output of `from` is fed back
+    /// to the input of `to`. In most algorithms an operator
+    /// feeds back to itself, so `from` and `to` are the same.
+    pub feedback: (usize, usize),
+}
+
+// Modulation edges, carriers and feedback for each algorithm,
+// as in the diagrams in `ALGORITHM_DIAGRAMS`.
+type TopologyEntry = (&'static [(usize, usize)], &'static [usize], (usize, usize));
+
+static TOPOLOGIES: [TopologyEntry; 32] = [
+    (&[(2, 1), (4, 3), (5, 4), (6, 5)], &[1, 3], (6, 6)),  // 1
+    (&[(2, 1), (4, 3), (5, 4), (6, 5)], &[1, 3], (2, 2)),  // 2
+    (&[(2, 1), (3, 2), (5, 4), (6, 5)], &[1, 4], (6, 6)),  // 3
+    (&[(2, 1), (3, 2), (5, 4), (6, 5)], &[1, 4], (4, 6)),  // 4
+    (&[(2, 1), (4, 3), (6, 5)], &[1, 3, 5], (6, 6)),  // 5
+    (&[(2, 1), (4, 3), (6, 5)], &[1, 3, 5], (5, 6)),  // 6
+    (&[(2, 1), (4, 3), (5, 3), (6, 5)], &[1, 3], (6, 6)),  // 7
+    (&[(2, 1), (4, 3), (5, 3), (6, 5)], &[1, 3], (4, 4)),  // 8
+    (&[(2, 1), (4, 3), (5, 3), (6, 5)], &[1, 3], (2, 2)),  // 9
+    (&[(2, 1), (3, 2), (5, 4), (6, 4)], &[1, 4], (3, 3)),  // 10
+    (&[(2, 1), (3, 2), (5, 4), (6, 4)], &[1, 4], (6, 6)),  // 11
+    (&[(2, 1), (4, 3), (5, 3), (6, 3)], &[1, 3], (2, 2)),  // 12
+    (&[(2, 1), (4, 3), (5, 3), (6, 3)], &[1, 3], (6, 6)),  // 13
+    (&[(2, 1), (4, 3), (5, 4), (6, 4)], &[1, 3], (6, 6)),  // 14
+    (&[(2, 1), (4, 3), (5, 4), (6, 4)], &[1, 3], (2, 2)),  // 15
+    (&[(2, 1), (3, 1), (4, 3), (5, 1), (6, 5)], &[1], (6, 6)),  // 16
+    (&[(2, 1), (3, 1), (4, 3), (5, 1), (6, 5)], &[1], (2, 2)),  // 17
+    (&[(2, 1), (3, 1), (4, 1), (5, 4), (6, 5)], &[1], (3, 3)),  // 18
+    (&[(2, 1), (3, 2), (6, 4), (6, 5)], &[1, 4, 5], (6, 6)),  // 19
+    (&[(3, 1), (3, 2), (5, 4), (6, 4)], &[1, 2, 4], (3, 3)),  // 20
+    (&[(3, 1), (3, 2), (6, 4), (6, 5)], &[1, 2, 4, 5], (3, 3)),  // 21
+    (&[(2, 1), (6, 3), (6, 4), (6, 5)], &[1, 3, 4, 5], (6, 6)),  // 22
+    (&[(3, 2), (6, 4), (6, 5)], &[1, 2, 4, 5], (6, 6)),  // 23
+    (&[(6, 3), (6, 4), (6, 5)], &[1, 2, 3, 4, 5], (6, 6)),  // 24
+    (&[(6, 4), (6, 5)], &[1, 2, 3, 4, 5], (6, 6)),  // 25
+    (&[(3, 2), (5, 4), (6, 4)], &[1, 2, 4], (6, 6)),  // 26
+    (&[(3, 2), (5, 4), (6, 4)], &[1, 2, 4], (3, 3)),  // 27
+    (&[(2, 1), (4, 3), (5, 4)], &[1, 3, 6], (5, 5)),  // 28
+    (&[(4, 3), (6, 5)], &[1, 2, 3, 5], (6, 6)),  // 29
+    (&[(4, 3), (5, 4)], &[1, 2, 3, 6], (5, 5)),  // 30
+    (&[(6, 5)], &[1, 2, 3, 4, 5], (6, 6)),  // 31
+    (&[], &[1, 2, 3, 4, 5, 6], (6, 6)),  // 32
+];
+
+impl Algorithm {
+    /// Gets the operator routing of this algorithm.
+    pub fn topology(&self) -> Topology {
+        let (edges, carriers, feedback) = TOPOLOGIES[self.value() as usize - 1];
+        Topology {
+            carriers: carriers.to_vec(),
+            edges: edges.to_vec(),
+            feedback,
+        }
+    }
+}
+
+impl Topology {
+    /// Gets the carrier operators.
+    pub fn carriers(&self) -> Vec<usize> {
+        self.carriers.clone()
+    }
+
+    /// Returns `true` if `op` is a carrier.
+    pub fn is_carrier(&self, op: usize) -> bool {
+        self.carriers.contains(&op)
+    }
+
+    /// Gets the operators that modulate `op`, not counting feedback.
+    pub fn modulators_of(&self, op: usize) -> Vec<usize> {
+        self.edges.iter().filter(|(_, to)| *to == op).map(|(from, _)| *from).collect()
+    }
+
+    /// Gets the operators that `op` modulates, not counting feedback.
+    pub fn targets_of(&self, op: usize) -> Vec<usize> {
+        self.edges.iter().filter(|(from, _)| *from == op).map(|(_, to)| *to).collect()
+    }
+
+    /// Gets the operators in the feedback loop, in ascending order.
+    /// For self-feedback this is just the one operator.
+    pub fn feedback_operators(&self) -> Vec<usize> {
+        let (from, to) = self.feedback;
+        (to.min(from)..=from.max(to)).collect()
+    }
+
+    /// Gets the number of operators in the longest modulation chain,
+    /// from a carrier up to its topmost modulator.
+    pub fn stack_depth(&self) -> usize {
+        self.carriers.iter().map(|&op| self.depth_of(op)).max().unwrap_or(0)
+    }
+
+    // Gets the depth of the modulation chain ending at `op`.
+    fn depth_of(&self, op: usize) -> usize {
+        1 + self.modulators_of(op).iter().map(|&m| self.depth_of(m)).max().unwrap_or(0)
+    }
+
+    /// Gets the operators in the order they need to be computed,
+    /// with every modulator before the operators it modulates.
+    pub fn evaluation_order(&self) -> Vec<usize> {
+        // The DX7 algorithms are arranged so that modulators always have
+        // higher numbers than the operators they modulate.
+        (1..=OPERATOR_COUNT).rev().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_algorithm_1() {
+        let topology = Algorithm::new(1).topology();
+        assert_eq!(topology.carriers(), vec![1, 3]);
+        assert_eq!(topology.modulators_of(3), vec![4]);
+        assert_eq!(topology.targets_of(6), vec![5]);
+        assert_eq!(topology.feedback_operators(), vec![6]);
+        assert_eq!(topology.stack_depth(), 4);
+    }
+
+    #[test]
+    fn test_feedback_loops() {
+        assert_eq!(Algorithm::new(4).topology().feedback, (4, 6));
+        assert_eq!(Algorithm::new(4).topology().feedback_operators(), vec![4, 5, 6]);
+        assert_eq!(Algorithm::new(6).topology().feedback_operators(), vec![5, 6]);
+    }
+
+    #[test]
+    fn test_all_topologies() {
+        for alg in 1..=32 {
+            let topology = Algorithm::new(alg).topology();
+            for op in 1..=OPERATOR_COUNT {
+                // Every operator is either a carrier or modulates another one.
+                assert!(topology.is_carrier(op) || !topology.targets_of(op).is_empty(),
+                    "algorithm {} operator {}", alg, op);
+            }
+            for (from, to) in &topology.edges {
+                assert!(from > to, "algorithm {}", alg);
+            }
+        }
+        assert_eq!(Algorithm::new(32).topology().stack_depth(), 1);
+    }
+}
@@ -0,0 +1,126 @@
+use syxpack::Ranged;
+
+use crate::dx7::{
+    Depth,
+    Note,
+    Velocity,
+};
+use crate::dx7::lfo::Lfo;
+use crate::dx7::loudness::SILENCE_THRESHOLD;
+use crate::dx7::operator::Operator;
+use crate::dx7::voice::{
+    Voice,
+    OPERATOR_COUNT,
+};
+
+impl Operator {
+    /// Returns `true` if the operator is below the silence threshold
+    /// for every note, even at full velocity.
+    pub fn is_muted(&self) -> bool {
+        (Note::FIRST..=Note::LAST).all(|note|
+            self.loudness(Note::new(note), Velocity::new(Velocity::LAST), 0.0) < SILENCE_THRESHOLD)
+    }
+}
+
+impl Voice {
+    /// Gets the numbers of the operators that can be heard: carriers that
+    /// are not muted, and unmuted modulators of audible operators.
+    /// An operator is muted if its output level is too low to be heard
+    /// at any note, taking keyboard level scaling into account.
+    pub fn audible_operators(&self) -> Vec<usize> {
+        let topology = self.alg.topology();
+        let mut audible = [false; OPERATOR_COUNT + 1];
+        // Operators are evaluated with modulators first, so walk
+        // the evaluation order backwards from the carriers.
+        for op in topology.evaluation_order().into_iter().rev() {
+            let reaches_output = topology.is_carrier(op)
+                || topology.targets_of(op).iter().any(|&target| audible[target]);
+            audible[op] = reaches_output && !self.operators[op - 1].is_muted();
+        }
+        (1..=OPERATOR_COUNT).filter(|&op| audible[op]).collect()
+    }
+
+    /// Makes a copy of the voice with the parameters that cannot be heard
+    /// set to their initial values, so that voices that sound the same
+    /// have the same SysEx data apart from the name:
+    ///
+    /// - inaudible operators are reset to the initial operator,
+    /// - feedback is cleared if no operator in the feedback loop is audible,
+    /// - the LFO is reset if both PMD and AMD are zero.
+    ///
+    /// Modulation from the wheel and other controllers is not considered.
+    pub fn canonicalize(&self) -> Voice {
+        let audible = self.audible_operators();
+        let mut voice = self.clone();
+        for op in 1..=OPERATOR_COUNT {
+            if !audible.contains(&op) {
+                voice.operators[op - 1] = Operator::new();
+            }
+        }
+
+        let feedback = self.alg.topology().feedback_operators();
+        if !feedback.iter().any(|op| audible.contains(op)) {
+            voice.feedback = Depth::new(0);
+        }
+
+        if self.lfo.pmd.value() == 0 && self.lfo.amd.value() == 0 {
+            voice.lfo = Lfo::new();
+        }
+        voice
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syxpack::SystemExclusiveData;
+    use crate::dx7::{
+        Algorithm,
+        Level,
+    };
+    use crate::dx7::envelope::Rate;
+    use crate::dx7::lfo::LfoWaveform;
+
+    #[test]
+    fn test_audible_operators() {
+        // In algorithm 32 every operator is a carrier, and only OP1 is on.
+        let mut voice = Voice::new();
+        voice.alg = Algorithm::new(32);
+        voice.operators[0].output_level = Level::new(99);
+        assert_eq!(voice.audible_operators(), vec![1]);
+
+        // A modulator is heard only through an audible operator.
+        voice.alg = Algorithm::new(1);
+        voice.operators[1].output_level = Level::new(80);
+        voice.operators[3].output_level = Level::new(80);
+        assert_eq!(voice.audible_operators(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        // Algorithm 1 has OP6 feeding back to itself.
+        let mut voice = Voice::new();
+        voice.alg = Algorithm::new(1);
+        voice.operators[0].output_level = Level::new(99);
+        voice.operators[5].eg.rates[0] = Rate::new(20);
+        voice.feedback = Depth::new(7);
+        voice.lfo.speed = Level::new(80);
+        voice.lfo.waveform = LfoWaveform::Sine;
+
+        let canonical = voice.canonicalize();
+        assert_eq!(canonical.operators[5].eg.rates[0].value(), 99);
+        assert_eq!(canonical.feedback.value(), 0);
+        assert_eq!(canonical.lfo.speed.value(), 35);
+        assert_eq!(canonical.name.value(), voice.name.value());
+        assert_eq!(canonical.canonicalize().to_bytes(), canonical.to_bytes());
+
+        // With audible feedback and LFO modulation nothing changes.
+        voice.operators[1].output_level = Level::new(50);
+        voice.operators[5].output_level = Level::new(50);
+        voice.operators[4].output_level = Level::new(50);
+        voice.operators[3].output_level = Level::new(50);
+        voice.operators[2].output_level = Level::new(99);
+        voice.lfo.pmd = Level::new(10);
+        assert_eq!(voice.canonicalize().to_bytes(), voice.to_bytes());
+    }
+}
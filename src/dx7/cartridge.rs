@@ -1,11 +1,15 @@
 use log::debug;
-use dbg_hex::dbg_hex;
 
 use syxpack::{
     ParseError,
     SystemExclusiveData,
 };
 
+use crate::dx7::{
+    check_length,
+    nested_error,
+};
+use crate::dx7::policy::ParseContext;
 use crate::dx7::voice::{
     Voice,
     VOICE_PACKED_SIZE
@@ -15,7 +19,8 @@ pub const VOICE_COUNT: usize = 32;
 pub const CARTRIDGE_DATA_SIZE: usize = 4096;
 
 /// A DX7 cartridge with 32 voices.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Cartridge {
     pub voices: Vec<Voice>,
 }
@@ -28,25 +33,48 @@ impl Default for Cartridge {
     }
 }
 
-impl SystemExclusiveData for Cartridge {
-    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+// Converts an error in unpacked voice data to one that refers
+// to the packed voice at `offset` in the cartridge data.
+fn packed_error(error: ParseError, offset: usize, index: usize) -> ParseError {
+    match error {
+        ParseError::InvalidData(voice_offset, message) => {
+            let packed_offset = offset + Voice::packed_offset(voice_offset as usize);
+            nested_error(
+                ParseError::InvalidData(0, message),
+                packed_offset,
+                &format!("voices[{}]", index))
+        },
+        other => other,
+    }
+}
+
+impl Cartridge {
+    /// Makes a cartridge from SysEx bytes, handling out-of-range values
+    /// according to the context policy. Any repairs are recorded with
+    /// the index of the voice they were made in.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, CARTRIDGE_DATA_SIZE, "cartridge")?;
+
         let mut offset = 0;
         let mut voices = Vec::<Voice>::new();
-        for _i in 0..VOICE_COUNT {
-            //eprintln!("VOICE {}", i + 1);
+        for i in 0..VOICE_COUNT {
             let packed_voice_data = &data[offset..offset + VOICE_PACKED_SIZE];
-            //eprintln!("Packed voice data length = {}", packed_voice_data.len());
-            //dbg_hex!(&packed_voice_data);
             let voice_data = Voice::unpack(packed_voice_data);
-            //eprintln!("Unpacked voice data length = {}", voice_data.len());
-            //dbg_hex!(&voice_data);
-            let voice = Voice::parse(&voice_data).expect("valid voice data");
-            //eprintln!("name = {}", voice.name);
+            context.voice = Some(i);
+            let result = Voice::parse_with(&voice_data, context);
+            context.voice = None;
+            let voice = result.map_err(|e| packed_error(e, offset, i))?;
             voices.push(voice);
             offset += VOICE_PACKED_SIZE;
         }
         Ok(Cartridge { voices })
     }
+}
+
+impl SystemExclusiveData for Cartridge {
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
 
     fn to_bytes(&self) -> Vec<u8> {
         let mut data: Vec<u8> = Vec::new();
@@ -70,6 +98,33 @@ impl SystemExclusiveData for Cartridge {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_short_data() {
+        let data = vec![0u8; 100];
+        assert_eq!(
+            Cartridge::parse(&data).unwrap_err(),
+            ParseError::InvalidData(100,
+                "cartridge: got 100 bytes of data, expected 4096 bytes".to_string()));
+    }
+
+    #[test]
+    fn test_parse_bad_name_offset() {
+        let mut data = Cartridge::default().to_bytes();
+        // The name is in the last ten bytes of the packed voice.
+        let offset = 2 * VOICE_PACKED_SIZE + 118 + 4;
+        data[offset] = 0xff;
+        assert_eq!(
+            Cartridge::parse_with(&data, &mut ParseContext::strict()).unwrap_err(),
+            ParseError::InvalidData(offset as u32,
+                "voices[2].name: invalid character".to_string()));
+
+        let mut context = ParseContext::lenient();
+        let cartridge = Cartridge::parse_with(&data, &mut context).expect("lenient parse");
+        assert_eq!(cartridge.voices[2].name.value(), "INIT VOICE");
+        assert_eq!(context.repairs.len(), 1);
+        assert_eq!(context.repairs[0].voice, Some(2));
+    }
+
     #[test]
     fn test_cartridge_length() {
         let cartridge = Cartridge::default();
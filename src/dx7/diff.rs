@@ -0,0 +1,312 @@
+use std::fmt;
+
+use syxpack::SystemExclusiveData;
+
+use crate::dx7::cartridge::Cartridge;
+use crate::dx7::parameter::{
+    OperatorParameter,
+    VoiceParameter,
+};
+use crate::dx7::voice::Voice;
+
+/// A changed parameter, with its path in the voice model
+/// (like `op3.eg.rates[1]`) and the old and new values as displayed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.path, self.old, self.new)
+    }
+}
+
+/// The parameters that differ between two voices, in SysEx order.
+#[derive(Debug, Clone, Default)]
+pub struct VoiceDiff {
+    pub changes: Vec<Change>,
+}
+
+impl VoiceDiff {
+    /// Returns `true` if the voices have the same parameters.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for VoiceDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.changes.is_empty() {
+            return writeln!(f, "no changes");
+        }
+        let width = self.changes.iter().map(|c| c.path.len()).max().unwrap_or(0);
+        for change in &self.changes {
+            writeln!(f, "{:<width$}  {} -> {}", change.path, change.old, change.new, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+// Gets the value of `parameter` in `voice` as displayed.
+fn parameter_value(voice: &Voice, parameter: VoiceParameter) -> String {
+    match parameter {
+        VoiceParameter::Operator(op, param) => {
+            let operator = &voice.operators[op - 1];
+            let scaling = &operator.kbd_level_scaling;
+            match param {
+                OperatorParameter::EgRate(i) => operator.eg.rates[i].to_string(),
+                OperatorParameter::EgLevel(i) => operator.eg.levels[i].to_string(),
+                OperatorParameter::Breakpoint => scaling.breakpoint.name(),
+                OperatorParameter::LeftDepth => scaling.left.depth.to_string(),
+                OperatorParameter::RightDepth => scaling.right.depth.to_string(),
+                OperatorParameter::LeftCurve => scaling.left.curve.to_string(),
+                OperatorParameter::RightCurve => scaling.right.curve.to_string(),
+                OperatorParameter::KbdRateScaling => operator.kbd_rate_scaling.to_string(),
+                OperatorParameter::AmpModSens => operator.amp_mod_sens.to_string(),
+                OperatorParameter::KeyVelSens => operator.key_vel_sens.to_string(),
+                OperatorParameter::OutputLevel => operator.output_level.to_string(),
+                OperatorParameter::Mode => operator.mode.to_string(),
+                OperatorParameter::Coarse => operator.coarse.to_string(),
+                OperatorParameter::Fine => operator.fine.to_string(),
+                OperatorParameter::Detune => operator.detune.to_string(),
+            }
+        },
+        VoiceParameter::PegRate(i) => voice.peg.rates[i].to_string(),
+        VoiceParameter::PegLevel(i) => voice.peg.levels[i].to_string(),
+        VoiceParameter::Algorithm => voice.alg.to_string(),
+        VoiceParameter::Feedback => voice.feedback.to_string(),
+        VoiceParameter::OscSync => voice.osc_sync.to_string(),
+        VoiceParameter::LfoSpeed => voice.lfo.speed.to_string(),
+        VoiceParameter::LfoDelay => voice.lfo.delay.to_string(),
+        VoiceParameter::LfoPmd => voice.lfo.pmd.to_string(),
+        VoiceParameter::LfoAmd => voice.lfo.amd.to_string(),
+        VoiceParameter::LfoSync => voice.lfo.sync.to_string(),
+        VoiceParameter::LfoWaveform => voice.lfo.waveform.to_string(),
+        VoiceParameter::PitchModSens => voice.pitch_mod_sens.to_string(),
+        VoiceParameter::Transpose => voice.transpose.to_string(),
+        VoiceParameter::NameChar(i) => format!("{:?}", voice.name.value().chars().nth(i).unwrap_or(' ')),
+    }
+}
+
+impl Voice {
+    /// Compares this voice with `other`, listing the parameters
+    /// that `other` changes. The parameters are compared in their
+    /// SysEx form, so the paths are the same as in parameter change
+    /// messages and lint findings.
+    pub fn diff(&self, other: &Voice) -> VoiceDiff {
+        let (old_data, new_data) = (self.to_bytes(), other.to_bytes());
+        let changes = VoiceParameter::all().into_iter()
+            .filter(|parameter| {
+                let number = parameter.number().expect("valid parameter") as usize;
+                old_data[number] != new_data[number]
+            })
+            .map(|parameter| Change {
+                path: parameter.path(),
+                old: parameter_value(self, parameter),
+                new: parameter_value(other, parameter),
+            })
+            .collect();
+        VoiceDiff { changes }
+    }
+}
+
+/// How a voice in one cartridge corresponds to a voice in another.
+/// Voice numbers are indexes into the cartridge voices, starting at 0.
+#[derive(Debug, Clone)]
+pub enum VoiceChange {
+    /// The voice is identical, possibly at a different position.
+    Unchanged { old: usize, new: usize, name: String },
+
+    /// The voice was matched by name, but has changes.
+    Modified { old: usize, new: usize, name: String, diff: VoiceDiff },
+
+    /// The old voice has no counterpart in the new cartridge.
+    Removed { old: usize, name: String },
+
+    /// The new voice has no counterpart in the old cartridge.
+    Added { new: usize, name: String },
+}
+
+/// The differences between two cartridges, with voices aligned
+/// by content, then by name.
+#[derive(Debug, Clone)]
+pub struct CartridgeDiff {
+    pub voices: Vec<VoiceChange>,
+}
+
+impl CartridgeDiff {
+    /// Returns `true` if every voice is unchanged and in the same position.
+    pub fn is_empty(&self) -> bool {
+        self.voices.iter().all(|change| matches!(change,
+            VoiceChange::Unchanged { old, new, .. } if old == new))
+    }
+}
+
+impl fmt::Display for CartridgeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no changes");
+        }
+        for change in &self.voices {
+            match change {
+                VoiceChange::Unchanged { old, new, name } => {
+                    if old != new {
+                        writeln!(f, "{:2} {}: moved from {}", new + 1, name, old + 1)?;
+                    }
+                },
+                VoiceChange::Modified { old, new, name, diff } => {
+                    if old != new {
+                        writeln!(f, "{:2} {}: moved from {}, {} changes", new + 1, name, old + 1, diff.changes.len())?;
+                    } else {
+                        writeln!(f, "{:2} {}: {} changes", new + 1, name, diff.changes.len())?;
+                    }
+                    for line in diff.to_string().lines() {
+                        writeln!(f, "     {}", line)?;
+                    }
+                },
+                VoiceChange::Removed { old, name } => {
+                    writeln!(f, "{:2} {}: removed", old + 1, name)?;
+                },
+                VoiceChange::Added { new, name } => {
+                    writeln!(f, "{:2} {}: added", new + 1, name)?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+// Finds the unmatched old voice that satisfies `matches`, preferring
+// the one at the same position as the new voice.
+fn find_match(new: usize, matched: &[bool], matches: impl Fn(usize) -> bool) -> Option<usize> {
+    if new < matched.len() && !matched[new] && matches(new) {
+        return Some(new);
+    }
+    (0..matched.len()).find(|&old| !matched[old] && matches(old))
+}
+
+impl Cartridge {
+    /// Compares this cartridge with `other`. Voices are aligned first by
+    /// identical content and then by name, so that reordered and edited
+    /// voices are reported as such. When several voices match, the one
+    /// in the same position is preferred.
+    pub fn diff(&self, other: &Cartridge) -> CartridgeDiff {
+        let old_data: Vec<Vec<u8>> = self.voices.iter().map(|voice| voice.to_bytes()).collect();
+        let new_data: Vec<Vec<u8>> = other.voices.iter().map(|voice| voice.to_bytes()).collect();
+        let old_names: Vec<String> = self.voices.iter().map(|voice| voice.name.value().trim().to_string()).collect();
+        let new_names: Vec<String> = other.voices.iter().map(|voice| voice.name.value().trim().to_string()).collect();
+
+        let mut matched = vec![false; self.voices.len()];
+        let mut alignment: Vec<Option<usize>> = vec![None; other.voices.len()];
+        let passes: [&dyn Fn(usize, usize) -> bool; 2] = [
+            &|old, new| old_data[old] == new_data[new],
+            &|old, new| old_names[old] == new_names[new],
+        ];
+        for pass in passes {
+            for (new, aligned) in alignment.iter_mut().enumerate() {
+                if aligned.is_none() {
+                    *aligned = find_match(new, &matched, |old| pass(old, new));
+                    if let Some(old) = *aligned {
+                        matched[old] = true;
+                    }
+                }
+            }
+        }
+
+        let mut voices = Vec::new();
+        for (new, old) in alignment.into_iter().enumerate() {
+            let name = other.voices[new].name.value();
+            voices.push(match old {
+                Some(old) => {
+                    let diff = self.voices[old].diff(&other.voices[new]);
+                    if diff.is_empty() {
+                        VoiceChange::Unchanged { old, new, name }
+                    } else {
+                        VoiceChange::Modified { old, new, name, diff }
+                    }
+                },
+                None => VoiceChange::Added { new, name },
+            });
+        }
+        for (old, _) in matched.iter().enumerate().filter(|(_, m)| !**m) {
+            voices.push(VoiceChange::Removed { old, name: self.voices[old].name.value() });
+        }
+        CartridgeDiff { voices }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syxpack::Ranged;
+    use crate::dx7::{
+        Detune,
+        Level,
+        rom1a,
+    };
+    use crate::dx7::envelope::Rate;
+    use crate::dx7::operator::Key;
+    use crate::dx7::voice::VoiceName;
+
+    #[test]
+    fn test_voice_diff() {
+        let voice = Voice::new();
+        assert!(voice.diff(&voice).is_empty());
+        assert_eq!(voice.diff(&voice).to_string(), "no changes\n");
+
+        let mut other = voice.clone();
+        other.operators[2].eg.rates[1] = Rate::new(50);
+        other.lfo.speed = Level::new(20);
+        let diff = voice.diff(&other);
+        assert_eq!(diff.changes, vec![
+            Change { path: "op3.eg.rates[1]".to_string(), old: "99".to_string(), new: "50".to_string() },
+            Change { path: "lfo.speed".to_string(), old: "35".to_string(), new: "20".to_string() },
+        ]);
+        assert_eq!(diff.to_string(), "op3.eg.rates[1]  99 -> 50\nlfo.speed        35 -> 20\n");
+
+        // The changes follow the SysEx order, with OP6 first,
+        // and the name is compared character by character.
+        let mut other = voice.clone();
+        other.operators[0].kbd_level_scaling.breakpoint = Key::new(51);
+        other.operators[5].detune = Detune::new(3);
+        other.name = VoiceName::new("INIT VOICF");
+        let changes: Vec<String> = voice.diff(&other).changes.iter()
+            .map(|change| change.to_string())
+            .collect();
+        assert_eq!(changes, vec![
+            "op6.detune: 0 -> 3",
+            "op1.kbd_level_scaling.breakpoint: C3 -> C4",
+            "name[9]: 'E' -> 'F'",
+        ]);
+    }
+
+    #[test]
+    fn test_cartridge_diff() {
+        let cartridge = rom1a();
+        assert!(cartridge.diff(&cartridge).is_empty());
+
+        // Swap two voices, edit one, and replace another.
+        let mut other = cartridge.clone();
+        other.voices.swap(0, 1);
+        other.voices[2].operators[0].output_level = Level::new(50);
+        other.voices[3] = Voice { name: VoiceName::new("NEW VOICE"), ..Voice::new() };
+
+        let diff = cartridge.diff(&other);
+        assert!(!diff.is_empty());
+        assert!(matches!(diff.voices[0], VoiceChange::Unchanged { old: 1, new: 0, .. }));
+        assert!(matches!(diff.voices[1], VoiceChange::Unchanged { old: 0, new: 1, .. }));
+        match &diff.voices[2] {
+            VoiceChange::Modified { old: 2, new: 2, diff, .. } => {
+                assert_eq!(diff.changes.len(), 1);
+                assert_eq!(diff.changes[0].path, "op1.output_level");
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(diff.voices[3], VoiceChange::Added { new: 3, .. }));
+        assert!(matches!(diff.voices.last(), Some(VoiceChange::Removed { old: 3, .. })));
+        assert!(diff.to_string().contains(" 1 BRASS   2 : moved from 2\n"));
+    }
+}
@@ -1,7 +1,6 @@
 use std::fmt;
 
 use rand::Rng;
-use log::warn;
 use syxpack::{
     ParseError,
     Ranged,
@@ -10,7 +9,13 @@ use syxpack::{
     SystemExclusiveData,
 };
 
-use crate::dx7::Level;
+use crate::dx7::{
+    Depth,
+    Level,
+    Note,
+    check_length,
+};
+use crate::dx7::policy::ParseContext;
 
 /// Envelope rate (0...99)
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -23,7 +28,8 @@ pub type Rates = [Rate; 4];
 pub type Levels = [Level; 4];
 
 /// Envelope generator.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Envelope {
     pub rates: Rates,
     pub levels: Levels,
@@ -85,6 +91,12 @@ impl Envelope {
     }
 }
 
+impl Default for Envelope {
+    fn default() -> Self {
+        Envelope::new()
+    }
+}
+
 impl fmt::Display for Envelope {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "R1={} L1={} R2={} L2={} R3={} L3={} R4={} L4={}",
@@ -95,38 +107,33 @@ impl fmt::Display for Envelope {
     }
 }
 
-impl SystemExclusiveData for Envelope {
-    /// Makes an envelope generator from relevant SysEx message bytes.
-    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+impl Envelope {
+    /// Makes an envelope generator from relevant SysEx message bytes,
+    /// handling out-of-range values according to the context policy.
+    /// In lenient mode the rates and levels are clamped.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, Self::data_size(), "envelope")?;
+
         let mut rates: Rates = [Default::default(); 4];
         let mut levels: Levels = [Default::default(); 4];
 
-        for i in 0 .. 4 {
-            let value = data[i] as i32;
-            rates[i] = Rate::new(if Rate::contains(value) {
-                value
-            } else {
-                let first = Rate::FIRST;
-                let last = Rate::LAST;
-                warn!("clamping SysEx value {value} to {first} ..= {last}");
-                num::clamp(value, Rate::FIRST, Rate::LAST)
-            });
+        for (i, rate) in rates.iter_mut().enumerate() {
+            *rate = context.clamped::<Rate>(data, i, &format!("rates[{}]", i))?;
         }
 
-        for i in 0 .. 4 {
-            let value = data[i + 4] as i32;
-            levels[i] = Level::new(if Level::contains(value) {
-                value
-            } else {
-                let first = Level::FIRST;
-                let last = Level::LAST;
-                warn!("clamping SysEx value {value} to {first} ..= {last}");
-                num::clamp(value, Level::FIRST, Level::LAST)
-            });
+        for (i, level) in levels.iter_mut().enumerate() {
+            *level = context.clamped::<Level>(data, i + 4, &format!("levels[{}]", i))?;
         }
 
         Ok(Envelope::new_rate_level(rates, levels))
     }
+}
+
+impl SystemExclusiveData for Envelope {
+    /// Makes an envelope generator from relevant SysEx message bytes.
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
 
     /// Gets the SysEx bytes of this EG.
     fn to_bytes(&self) -> Vec<u8> {
@@ -145,6 +152,202 @@ impl SystemExclusiveData for Envelope {
     fn data_size() -> usize { 8 }
 }
 
+// Internal envelope levels are in steps of 1/256 octave
+// (about 0.0235 dB), with full output at `FULL_LEVEL`.
+
+/// Internal envelope level at full output.
+pub const FULL_LEVEL: f64 = 3840.0;
+
+/// Internal envelope level below which the output is silent.
+pub const SILENT_LEVEL: f64 = 16.0;
+
+// Attacks jump over the inaudible range to this level.
+const ATTACK_START_LEVEL: f64 = 1716.0;
+
+// Attacks slow down as the level approaches this.
+const ATTACK_CEILING: f64 = 4352.0;
+
+static LEVEL_TABLE: [i32; 20] = [
+    0, 5, 9, 13, 17, 20, 23, 25, 27, 29, 31, 33, 35, 37, 39, 41, 42, 43, 45, 46
+];
+
+/// Scales a level 0...99 to the internal range 0...127.
+pub fn scale_output_level(level: i32) -> i32 {
+    if level >= 20 { 28 + level } else { LEVEL_TABLE[level as usize] }
+}
+
+/// Gets the internal level of an envelope stage at `level` 0...99,
+/// when the operator output level is `output_level` in the internal
+/// range 0...4064.
+pub fn stage_level(level: i32, output_level: i32) -> f64 {
+    let level = scale_output_level(level) >> 1;
+    ((level << 6) + output_level - 4256).max(SILENT_LEVEL as i32) as f64
+}
+
+/// Gets the internal output level of an operator at `level` 0...99.
+pub fn operator_output_level(level: i32) -> i32 {
+    scale_output_level(level).min(127) << 5
+}
+
+/// Gets the rate adjustment for `note` with keyboard rate scaling `depth`.
+pub fn keyboard_rate_scaling(note: Note, depth: Depth) -> i32 {
+    let group = (note.value() / 3 - 7).clamp(0, 31);
+    (depth.value() * group) >> 3
+}
+
+/// Gets the speed of an envelope stage with `rate` 0...99 in internal
+/// level units per second, adjusted by `rate_scaling`.
+pub fn level_speed(rate: i32, rate_scaling: i32) -> f64 {
+    let qrate = (((rate * 41) >> 6) + rate_scaling).min(63);
+    // The original adds this much to a 16.16 level every 64 samples at 44.1 kHz.
+    let increment = (4 + (qrate & 3)) << (2 + 6 + (qrate >> 2));
+    increment as f64 / 65536.0 * 44100.0 / 64.0
+}
+
+/// Moves `level` towards `target` for `seconds` at `speed`. Falling levels
+/// change linearly, rising levels jump over the inaudible range first and
+/// then slow down as they get louder.
+pub fn approach(level: f64, target: f64, speed: f64, seconds: f64) -> f64 {
+    if target <= level {
+        return (level - speed * seconds).max(target);
+    }
+
+    let mut level = level.max(ATTACK_START_LEVEL);
+    let mut remaining = seconds;
+    while level < target {
+        let slope = ((ATTACK_CEILING - level) / 256.0).ceil() - 1.0;
+        let end = target.min(ATTACK_CEILING - 256.0 * slope);
+        let time = (end - level) / (slope * speed);
+        if time > remaining {
+            return level + slope * speed * remaining;
+        }
+        remaining -= time;
+        level = end;
+    }
+    target
+}
+
+/// Gets the time in seconds it takes to move from `level` to `target`
+/// at `speed`, like in `approach`.
+pub fn travel_time(level: f64, target: f64, speed: f64) -> f64 {
+    if target <= level {
+        return (level - target) / speed;
+    }
+
+    let mut level = level.max(ATTACK_START_LEVEL);
+    let mut time = 0.0;
+    while level < target {
+        let slope = ((ATTACK_CEILING - level) / 256.0).ceil() - 1.0;
+        let end = target.min(ATTACK_CEILING - 256.0 * slope);
+        time += (end - level) / (slope * speed);
+        level = end;
+    }
+    time
+}
+
+/// Converts an internal level to an amplitude 0.0...1.0.
+pub fn amplitude(level: f64) -> f64 {
+    if level <= SILENT_LEVEL { 0.0 } else { 2.0_f64.powf((level - FULL_LEVEL) / 256.0) }
+}
+
+/// One stage of an envelope as it is played.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    /// Envelope stage 0...3, for R1/L1 to R4/L4.
+    pub stage: usize,
+
+    /// Start time in seconds from key on.
+    pub start: f64,
+
+    pub duration: f64,
+
+    /// Start and end levels in internal units.
+    pub from: f64,
+    pub to: f64,
+
+    /// Speed in internal level units per second.
+    pub speed: f64,
+}
+
+/// Envelope segments for one note, with the key held for `hold` seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeline {
+    pub segments: Vec<Segment>,
+    pub hold: f64,
+}
+
+impl Timeline {
+    /// Gets the duration of each stage in seconds. Stages cut short
+    /// by key off get their partial duration, and stages not reached
+    /// get zero.
+    pub fn durations(&self) -> [f64; 4] {
+        let mut result = [0.0; 4];
+        for segment in &self.segments {
+            result[segment.stage] += segment.duration;
+        }
+        result
+    }
+
+    /// Gets the time from key on to the end of the release.
+    pub fn duration(&self) -> f64 {
+        self.segments.last().map_or(0.0, |s| s.start + s.duration)
+    }
+
+    /// Gets the output amplitude 0.0...1.0 at `time` seconds from key on.
+    pub fn level_at(&self, time: f64) -> f64 {
+        // Between segments the level stays where the previous one ended.
+        let mut level = 0.0;
+        for segment in &self.segments {
+            if time < segment.start {
+                break;
+            }
+            let elapsed = (time - segment.start).min(segment.duration);
+            level = approach(segment.from, segment.to, segment.speed, elapsed);
+        }
+        amplitude(level)
+    }
+
+    /// Samples the output amplitude from key on to the end of the release.
+    pub fn sample(&self, sample_rate: f64) -> Vec<f64> {
+        let count = (self.duration() * sample_rate).ceil() as usize;
+        (0..count).map(|i| self.level_at(i as f64 / sample_rate)).collect()
+    }
+}
+
+impl Envelope {
+    /// Gets the segment timeline of this envelope for `note` with keyboard
+    /// rate scaling `rate_scaling`, when the key is held for `hold` seconds.
+    /// The operator is assumed to be at full output level.
+    pub fn timeline(&self, note: Note, rate_scaling: Depth, hold: f64) -> Timeline {
+        let output_level = operator_output_level(99);
+        let scaling = keyboard_rate_scaling(note, rate_scaling);
+        let mut segments = Vec::new();
+        let mut time = 0.0;
+        let mut level = 0.0;
+
+        let mut add_segment = |stage: usize, start: f64, limit: f64, level: &mut f64| {
+            let target = stage_level(self.levels[stage].value(), output_level);
+            let speed = level_speed(self.rates[stage].value(), scaling);
+            let duration = travel_time(*level, target, speed).min(limit);
+            let to = approach(*level, target, speed, duration);
+            segments.push(Segment { stage, start, duration, from: *level, to, speed });
+            *level = to;
+            duration
+        };
+
+        // Stages 1 to 3 run while the key is held, then the level sustains.
+        for stage in 0..3 {
+            if time >= hold {
+                break;
+            }
+            time += add_segment(stage, time, hold - time, &mut level);
+        }
+        add_segment(3, hold.max(time), f64::INFINITY, &mut level);
+
+        Timeline { segments, hold }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;  // import the names from outer scope
@@ -167,4 +370,32 @@ mod tests {
 
         assert_eq!(eg.to_string(), "R1=64 L1=32 R2=64 L2=32 R3=64 L3=32 R4=64 L4=32");
     }
+
+    #[test]
+    fn test_timeline() {
+        let eg = Envelope::adsr_int(50, 60, 80, 40);
+        let timeline = eg.timeline(Note::new(60), Depth::new(0), 2.0);
+        let durations = timeline.durations();
+        assert!(durations[0] > 0.0 && durations[0] < 1.0);
+        assert_eq!(durations[1], 0.0);  // already at L2 after the attack
+        assert!(durations[3] > 0.0);
+        assert_eq!(timeline.segments[3].start, 2.0);
+        assert_eq!(timeline.level_at(1.9), amplitude(stage_level(80, operator_output_level(99))));
+        assert_eq!(timeline.level_at(timeline.duration()), 0.0);
+
+        // Keyboard rate scaling makes higher notes faster.
+        let high = eg.timeline(Note::new(96), Depth::new(7), 2.0);
+        assert!(high.durations()[0] < durations[0]);
+    }
+
+    #[test]
+    fn test_timeline_short_hold() {
+        let eg = Envelope::adsr_int(20, 60, 80, 40);
+        let timeline = eg.timeline(Note::new(60), Depth::new(0), 0.01);
+        assert_eq!(timeline.segments.len(), 2);
+        assert_eq!(timeline.durations()[0], 0.01);
+        let samples = timeline.sample(1000.0);
+        assert_eq!(samples.len(), (timeline.duration() * 1000.0).ceil() as usize);
+        assert!(samples.iter().all(|&s| (0.0..=1.0).contains(&s)));
+    }
 }
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use syxpack::{
+    Ranged,
+    SystemExclusiveData,
+};
+
+use crate::dx7::cartridge::Cartridge;
+use crate::dx7::parameter::VoiceParameter;
+use crate::dx7::voice::{
+    Voice,
+    VOICE_NAME_LENGTH,
+    VOICE_SIZE,
+};
+
+/// Similarity at or above which `find_duplicates` groups two voices
+/// as near-identical.
+pub const DEFAULT_SIMILARITY_THRESHOLD: f64 = 0.95;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// Hashes `data` with 64-bit FNV-1a.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(FNV_OFFSET_BASIS, |hash, &b| (hash ^ b as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// A 64-bit hash of the voice parameters, displayed as 16 hex digits.
+/// The fingerprint is the same on every platform and in every version
+/// of the crate, so it can be stored.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct Fingerprint(pub u64);
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl Voice {
+    /// Gets the fingerprint of the voice parameters, not counting the name.
+    pub fn fingerprint(&self) -> Fingerprint {
+        let data = self.to_bytes();
+        Fingerprint(fnv1a(&data[..VOICE_SIZE - VOICE_NAME_LENGTH]))
+    }
+
+    /// Gets the fingerprint of the canonical voice, so that voices that
+    /// differ only in the name or in parameters that cannot be heard get
+    /// the same audible fingerprint.
+    pub fn audible_fingerprint(&self) -> Fingerprint {
+        self.canonicalize().fingerprint()
+    }
+
+    /// Gets the similarity of this voice and `other`, from 0.0 for voices
+    /// with every parameter at the opposite end of its range to 1.0 for
+    /// voices that sound the same. The voices are compared in canonical
+    /// form without the name, and every parameter has the same weight.
+    pub fn similarity(&self, other: &Voice) -> f64 {
+        let (data, other_data) = (self.canonicalize().to_bytes(), other.canonicalize().to_bytes());
+        let parameters: Vec<VoiceParameter> = VoiceParameter::all().into_iter()
+            .filter(|parameter| !matches!(parameter, VoiceParameter::NameChar(_)))
+            .collect();
+        let distance: f64 = parameters.iter().map(|parameter| {
+            let number = parameter.number().expect("valid parameter") as usize;
+            (data[number] as f64 - other_data[number] as f64).abs() / parameter.max_value() as f64
+        }).sum();
+        1.0 - distance / parameters.len() as f64
+    }
+}
+
+/// The position of a voice in a list of cartridges.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct VoiceLocation {
+    pub cartridge: usize,
+    pub voice: usize,
+}
+
+/// A group of identical or near-identical voices. The first member
+/// is the representative of the group, and every member is listed
+/// with its similarity to the representative.
+#[derive(Debug, Clone)]
+pub struct DuplicateGroup {
+    pub members: Vec<(VoiceLocation, f64)>,
+}
+
+/// Finds duplicate voices in `cartridges`. Voices with the same audible
+/// fingerprint are identical, and other voices are near-identical if their
+/// similarity to the representative of a group is at least `threshold`.
+/// Only voices with the same algorithm are compared for similarity.
+/// Returns the groups with more than one member, in the order of their
+/// first voice.
+pub fn find_duplicates(cartridges: &[Cartridge], threshold: f64) -> Vec<DuplicateGroup> {
+    let mut groups: Vec<(Voice, DuplicateGroup)> = Vec::new();
+    let mut by_fingerprint: HashMap<Fingerprint, (usize, f64)> = HashMap::new();
+    let mut by_algorithm: HashMap<i32, Vec<usize>> = HashMap::new();
+
+    for (cartridge_index, cartridge) in cartridges.iter().enumerate() {
+        for (voice_index, voice) in cartridge.voices.iter().enumerate() {
+            let location = VoiceLocation { cartridge: cartridge_index, voice: voice_index };
+            let fingerprint = voice.audible_fingerprint();
+            if let Some(&(index, similarity)) = by_fingerprint.get(&fingerprint) {
+                groups[index].1.members.push((location, similarity));
+                continue;
+            }
+
+            let candidates = by_algorithm.entry(voice.alg.value()).or_default();
+            let best = candidates.iter()
+                .map(|&index| (index, groups[index].0.similarity(voice)))
+                .filter(|&(_, similarity)| similarity >= threshold)
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            let (index, similarity) = best.unwrap_or_else(|| {
+                groups.push((voice.clone(), DuplicateGroup { members: Vec::new() }));
+                candidates.push(groups.len() - 1);
+                (groups.len() - 1, 1.0)
+            });
+            groups[index].1.members.push((location, similarity));
+            by_fingerprint.insert(fingerprint, (index, similarity));
+        }
+    }
+
+    groups.into_iter()
+        .map(|(_, group)| group)
+        .filter(|group| group.members.len() > 1)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dx7::{
+        Algorithm,
+        Level,
+        rom1a,
+    };
+    use crate::dx7::envelope::Rate;
+    use crate::dx7::voice::VoiceName;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fnv1a(b""), FNV_OFFSET_BASIS);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+
+        let voice = rom1a().voices[0].clone();
+        let renamed = Voice { name: VoiceName::new("RENAMED"), ..voice.clone() };
+        assert_eq!(voice.fingerprint(), renamed.fingerprint());
+        assert_eq!(voice.fingerprint().to_string().len(), 16);
+
+        let mut edited = voice.clone();
+        edited.operators[0].eg.rates[0] = Rate::new(1);
+        assert_ne!(voice.fingerprint(), edited.fingerprint());
+    }
+
+    #[test]
+    fn test_audible_fingerprint() {
+        // In algorithm 32 every operator is a carrier, and only OP1 is on.
+        let mut voice = Voice::new();
+        voice.alg = Algorithm::new(32);
+        voice.operators[0].output_level = Level::new(99);
+
+        let mut other = voice.clone();
+        other.operators[3].eg.rates[2] = Rate::new(10);
+        assert_ne!(voice.fingerprint(), other.fingerprint());
+        assert_eq!(voice.audible_fingerprint(), other.audible_fingerprint());
+        assert_eq!(voice.similarity(&other), 1.0);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let cartridge = rom1a();
+        let mut copy = cartridge.clone();
+        copy.voices[0].name = VoiceName::new("MY BRASS");
+        copy.voices[1].operators[0].fine = Level::new(1);
+
+        let groups = find_duplicates(std::slice::from_ref(&cartridge), 1.0);
+        assert!(groups.is_empty());
+
+        let groups = find_duplicates(&[cartridge, copy], DEFAULT_SIMILARITY_THRESHOLD);
+        let first = &groups[0].members;
+        assert_eq!(first[0], (VoiceLocation { cartridge: 0, voice: 0 }, 1.0));
+        assert!(first.contains(&(VoiceLocation { cartridge: 1, voice: 0 }, 1.0)));
+
+        let second = groups.iter()
+            .find(|group| group.members[0].0 == VoiceLocation { cartridge: 0, voice: 1 })
+            .unwrap();
+        let (location, similarity) = second.members.iter()
+            .find(|(location, _)| location.cartridge == 1)
+            .unwrap();
+        assert_eq!(location.voice, 1);
+        assert!(*similarity < 1.0 && *similarity > 0.99);
+    }
+}
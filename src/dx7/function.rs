@@ -0,0 +1,323 @@
+use std::fmt;
+
+use rand::Rng;
+use syxpack::{
+    ParseError,
+    Ranged,
+    ranged_impl,
+    Encoding,
+    SystemExclusiveData,
+};
+
+use crate::dx7::{
+    Level,
+    check_length,
+};
+use crate::dx7::policy::ParseContext;
+
+/// Size of the function parameter data (parameters 64...77).
+pub const FUNCTION_SIZE: usize = 14;
+
+/// Pitch bend range in semitones (0...12).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BendRange(i32);
+
+ranged_impl!(BendRange, 0, 12, 2);
+
+impl Encoding for BendRange { }  // identity mapping, no adjustment needed
+
+/// Pitch bend step (0...12). Zero means continuous bend.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BendStep(i32);
+
+ranged_impl!(BendStep, 0, 12, 0);
+
+impl Encoding for BendStep { }  // identity mapping, no adjustment needed
+
+/// Polyphony mode.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum PolyMode {
+    Poly,
+    Mono,
+}
+
+impl TryFrom<u8> for PolyMode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(PolyMode::Poly),
+            1 => Ok(PolyMode::Mono),
+            _ => Err("Bad poly mode value, expected 0...1")
+        }
+    }
+}
+
+impl From<PolyMode> for u8 {
+    fn from(mode: PolyMode) -> u8 {
+        mode as u8
+    }
+}
+
+impl fmt::Display for PolyMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            PolyMode::Poly => "poly",
+            PolyMode::Mono => "mono",
+        })
+    }
+}
+
+/// Portamento mode. The meaning depends on the polyphony mode:
+/// retain or follow in poly mode, fingered or full time in mono mode.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum PortamentoMode {
+    Retain,  // fingered in mono mode
+    Follow,  // full time in mono mode
+}
+
+impl TryFrom<u8> for PortamentoMode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(PortamentoMode::Retain),
+            1 => Ok(PortamentoMode::Follow),
+            _ => Err("Bad portamento mode value, expected 0...1")
+        }
+    }
+}
+
+impl From<PortamentoMode> for u8 {
+    fn from(mode: PortamentoMode) -> u8 {
+        mode as u8
+    }
+}
+
+impl fmt::Display for PortamentoMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            PortamentoMode::Retain => "retain",
+            PortamentoMode::Follow => "follow",
+        })
+    }
+}
+
+/// Destinations of a controller. In SysEx the assignments are
+/// packed into one byte (0...7): bit 0 = pitch, bit 1 = amplitude,
+/// bit 2 = EG bias.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct ControllerAssign {
+    pub pitch: bool,
+    pub amplitude: bool,
+    pub eg_bias: bool,
+}
+
+impl TryFrom<u8> for ControllerAssign {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        if value > 7 {
+            return Err("Bad controller assign value, expected 0...7");
+        }
+        Ok(ControllerAssign {
+            pitch: value & 0b001 != 0,
+            amplitude: value & 0b010 != 0,
+            eg_bias: value & 0b100 != 0,
+        })
+    }
+}
+
+impl From<ControllerAssign> for u8 {
+    fn from(assign: ControllerAssign) -> u8 {
+        (if assign.pitch { 0b001 } else { 0 })
+            | (if assign.amplitude { 0b010 } else { 0 })
+            | (if assign.eg_bias { 0b100 } else { 0 })
+    }
+}
+
+impl fmt::Display for ControllerAssign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if self.pitch {
+            parts.push("pitch");
+        }
+        if self.amplitude {
+            parts.push("amp");
+        }
+        if self.eg_bias {
+            parts.push("EG bias");
+        }
+        if parts.is_empty() {
+            write!(f, "off")
+        } else {
+            write!(f, "{}", parts.join("+"))
+        }
+    }
+}
+
+/// Controller settings: range and assignment.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct Controller {
+    pub range: Level,  // 0 ~ 99
+    pub assign: ControllerAssign,
+}
+
+impl fmt::Display for Controller {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "range = {}, assign = {}", self.range, self.assign)
+    }
+}
+
+/// DX7 function parameters (global settings).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Function {
+    pub poly_mode: PolyMode,
+    pub bend_range: BendRange,  // 0 ~ 12
+    pub bend_step: BendStep,  // 0 ~ 12
+    pub portamento_mode: PortamentoMode,
+    pub glissando: bool,
+    pub portamento_time: Level,  // 0 ~ 99
+    pub mod_wheel: Controller,
+    pub foot_control: Controller,
+    pub breath_control: Controller,
+    pub aftertouch: Controller,
+}
+
+impl Function {
+    /// Makes new function settings with the DX7 defaults.
+    pub fn new() -> Self {
+        Self {
+            poly_mode: PolyMode::Poly,
+            bend_range: BendRange::default(),
+            bend_step: BendStep::default(),
+            portamento_mode: PortamentoMode::Retain,
+            glissando: false,
+            portamento_time: Level::new(0),
+            mod_wheel: Controller::default(),
+            foot_control: Controller::default(),
+            breath_control: Controller::default(),
+            aftertouch: Controller::default(),
+        }
+    }
+
+    /// Makes function settings from SysEx bytes, handling out-of-range values
+    /// according to the context policy. The data is the values of function
+    /// parameters 64...77 in order.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, FUNCTION_SIZE, "function")?;
+
+        let mut controllers = [Controller::default(); 4];
+        let names = ["mod_wheel", "foot_control", "breath_control", "aftertouch"];
+        for (i, controller) in controllers.iter_mut().enumerate() {
+            let offset = 6 + i * 2;
+            *controller = context.nested(names[i], offset, |c| {
+                Ok(Controller {
+                    range: c.ranged::<Level>(&data[offset..], 0, "range")?,
+                    assign: c.choice(&data[offset..], 1, "assign",
+                        ControllerAssign::try_from, ControllerAssign::default())?,
+                })
+            })?;
+        }
+
+        Ok(Self {
+            poly_mode: context.choice(data, 0, "poly_mode", PolyMode::try_from, PolyMode::Poly)?,
+            bend_range: context.ranged::<BendRange>(data, 1, "bend_range")?,
+            bend_step: context.ranged::<BendStep>(data, 2, "bend_step")?,
+            portamento_mode: context.choice(data, 3, "portamento_mode",
+                PortamentoMode::try_from, PortamentoMode::Retain)?,
+            glissando: context.switch(data, 4, "glissando")?,
+            portamento_time: context.ranged::<Level>(data, 5, "portamento_time")?,
+            mod_wheel: controllers[0],
+            foot_control: controllers[1],
+            breath_control: controllers[2],
+            aftertouch: controllers[3],
+        })
+    }
+}
+
+impl Default for Function {
+    fn default() -> Self {
+        Function::new()
+    }
+}
+
+impl SystemExclusiveData for Function {
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        vec![
+            self.poly_mode.into(),
+            self.bend_range.encode(),
+            self.bend_step.encode(),
+            self.portamento_mode.into(),
+            if self.glissando { 1 } else { 0 },
+            self.portamento_time.encode(),
+            self.mod_wheel.range.encode(),
+            self.mod_wheel.assign.into(),
+            self.foot_control.range.encode(),
+            self.foot_control.assign.into(),
+            self.breath_control.range.encode(),
+            self.breath_control.assign.into(),
+            self.aftertouch.range.encode(),
+            self.aftertouch.assign.into(),
+        ]
+    }
+
+    fn data_size() -> usize { FUNCTION_SIZE }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Mode: {}
+Pitch bend: range = {}, step = {}
+Portamento: mode = {}, glissando = {}, time = {}
+Mod wheel: {}
+Foot control: {}
+Breath control: {}
+Aftertouch: {}",
+            self.poly_mode,
+            self.bend_range,
+            self.bend_step,
+            self.portamento_mode,
+            self.glissando,
+            self.portamento_time,
+            self.mod_wheel,
+            self.foot_control,
+            self.breath_control,
+            self.aftertouch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_function_round_trip() {
+        let function = Function {
+            poly_mode: PolyMode::Mono,
+            bend_range: BendRange::new(12),
+            portamento_time: Level::new(40),
+            breath_control: Controller {
+                range: Level::new(99),
+                assign: ControllerAssign { pitch: false, amplitude: true, eg_bias: true },
+            },
+            ..Function::new()
+        };
+        let data = function.to_bytes();
+        assert_eq!(data.len(), FUNCTION_SIZE);
+        assert_eq!(data[11], 0b110);
+        assert_eq!(Function::parse(&data).unwrap(), function);
+    }
+
+    #[test]
+    fn test_controller_assign_display() {
+        let assign = ControllerAssign::try_from(0b101).unwrap();
+        assert_eq!(assign.to_string(), "pitch+EG bias");
+    }
+}
@@ -1,7 +1,7 @@
+use std::f64::consts::PI;
 use std::fmt;
 
 use bit::BitIndex;
-use log::warn;
 use syxpack::{
     ParseError,
     Ranged,
@@ -9,10 +9,20 @@ use syxpack::{
     SystemExclusiveData,
 };
 
-use crate::dx7::Level;
+use crate::dx7::{
+    Depth,
+    Level,
+    Sensitivity,
+    check_length,
+};
+use crate::dx7::envelope::FULL_LEVEL;
+use crate::dx7::policy::ParseContext;
+use crate::dx7::voice::Voice;
 
 /// LFO waveform.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 #[repr(u8)]
 pub enum LfoWaveform {
     Triangle,
@@ -23,6 +33,45 @@ pub enum LfoWaveform {
     SampleAndHold,
 }
 
+impl TryFrom<u8> for LfoWaveform {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(LfoWaveform::Triangle),
+            1 => Ok(LfoWaveform::SawDown),
+            2 => Ok(LfoWaveform::SawUp),
+            3 => Ok(LfoWaveform::Square),
+            4 => Ok(LfoWaveform::Sine),
+            5 => Ok(LfoWaveform::SampleAndHold),
+            _ => Err("Bad LFO waveform value, expected 0...5")
+        }
+    }
+}
+
+impl From<LfoWaveform> for u8 {
+    fn from(waveform: LfoWaveform) -> u8 {
+        waveform as u8
+    }
+}
+
+impl LfoWaveform {
+    /// Gets the value 0.0...1.0 of the waveform at `phase` 0.0...1.0.
+    /// Sample and hold has no fixed shape, so it gives `None`;
+    /// use `LfoOscillator` for it instead.
+    pub fn value(&self, phase: f64) -> Option<f64> {
+        let p = phase.rem_euclid(1.0);
+        match self {
+            LfoWaveform::Triangle => Some(if p < 0.5 { 2.0 * p } else { 2.0 - 2.0 * p }),
+            LfoWaveform::SawDown => Some((1.5 - p).fract()),
+            LfoWaveform::SawUp => Some((p + 0.5).fract()),
+            LfoWaveform::Square => Some(if p < 0.5 { 1.0 } else { 0.0 }),
+            LfoWaveform::Sine => Some(0.5 + (2.0 * PI * p).sin() / 2.0),
+            LfoWaveform::SampleAndHold => None,
+        }
+    }
+}
+
 impl fmt::Display for LfoWaveform {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let printable = match *self {
@@ -39,10 +88,13 @@ impl fmt::Display for LfoWaveform {
 
 /// LFO.
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Lfo {
     pub speed: Level,  // 0 ~ 99
     pub delay: Level,  // 0 ~ 99
+    #[cfg_attr(feature = "serde", serde(rename = "pitch_mod_depth"))]
     pub pmd: Level,    // 0 ~ 99
+    #[cfg_attr(feature = "serde", serde(rename = "amp_mod_depth"))]
     pub amd: Level,    // 0 ~ 99
     pub sync: bool,
     pub waveform: LfoWaveform,
@@ -87,6 +139,185 @@ impl Lfo {
     }
 }
 
+// LFO rates are in units of 1/2^32 cycle.
+const RATE_UNIT: f64 = 25190424.0 / 4294967296.0;
+
+static PITCH_MOD_SENS_TABLE: [f64; 8] = [0.0, 10.0, 20.0, 33.0, 55.0, 92.0, 153.0, 255.0];
+
+static AMP_MOD_SENS_TABLE: [f64; 4] = [0.0, 0.2588, 0.4274, 1.0];
+
+impl Lfo {
+    /// Gets the LFO frequency in hertz.
+    pub fn frequency(&self) -> f64 {
+        let speed = self.speed.value();
+        let mut rate = if speed == 0 { 1 } else { (165 * speed) >> 6 };
+        rate *= if rate < 160 { 11 } else { 11 + ((rate - 160) >> 4) };
+        rate as f64 * RATE_UNIT
+    }
+
+    // Gets the speeds of the two halves of the delay, in delay cycles
+    // per second. The first half is silent, the second fades the LFO in.
+    fn delay_speeds(&self) -> Option<(f64, f64)> {
+        let a = 99 - self.delay.value();
+        if a == 99 {
+            return None;
+        }
+        let first = (16 + (a & 15)) << (1 + (a >> 4));
+        let second = (first & 0xff80).max(0x80);
+        Some((first as f64 * RATE_UNIT, second as f64 * RATE_UNIT))
+    }
+
+    /// Gets the time in seconds from key on until the LFO starts.
+    pub fn delay_time(&self) -> f64 {
+        self.delay_speeds().map_or(0.0, |(first, _)| 0.5 / first)
+    }
+
+    /// Gets the time in seconds the LFO takes to fade in
+    /// to full depth after the delay.
+    pub fn fade_in_time(&self) -> f64 {
+        self.delay_speeds().map_or(0.0, |(_, second)| 0.5 / second)
+    }
+
+    /// Gets the pitch modulation in octaves for the LFO value `lfo`
+    /// and the delay fade-in `delay`, both 0.0...1.0, with the voice
+    /// pitch modulation sensitivity `sensitivity`.
+    pub fn pitch_modulation(&self, sensitivity: Depth, lfo: f64, delay: f64) -> f64 {
+        let depth = ((self.pmd.value() * 165) >> 6) as f64;
+        let sensitivity = PITCH_MOD_SENS_TABLE[sensitivity.value() as usize];
+        depth * sensitivity * delay * (lfo - 0.5) / 32768.0
+    }
+
+    /// Gets the fraction 0.0...1.0 by which the LFO reduces the envelope
+    /// level of an operator with the amplitude modulation sensitivity
+    /// `sensitivity`, for the LFO value `lfo` and the delay fade-in `delay`.
+    pub fn amplitude_modulation(&self, sensitivity: Sensitivity, lfo: f64, delay: f64) -> f64 {
+        let sensitivity = AMP_MOD_SENS_TABLE[sensitivity.value() as usize];
+        if sensitivity == 0.0 {
+            return 0.0;
+        }
+        let depth = ((self.amd.value() * 165) >> 6) as f64 * delay * lfo / 256.0;
+        ((depth * sensitivity * 4.48 + 12.2).exp() / (1 << 24) as f64).min(1.0)
+    }
+
+    /// Gets the peak pitch deviation in cents, up or down, with the voice
+    /// pitch modulation sensitivity `sensitivity`.
+    pub fn vibrato_depth(&self, sensitivity: Depth) -> f64 {
+        1200.0 * self.pitch_modulation(sensitivity, 1.0, 1.0)
+    }
+
+    /// Gets the largest attenuation in decibels of an operator at full
+    /// output with the amplitude modulation sensitivity `sensitivity`.
+    pub fn tremolo_depth(&self, sensitivity: Sensitivity) -> f64 {
+        if self.amd.value() == 0 {
+            return 0.0;
+        }
+        let attenuation = FULL_LEVEL * self.amplitude_modulation(sensitivity, 1.0, 1.0);
+        attenuation / 256.0 * 20.0 * 2.0_f64.log10()
+    }
+}
+
+/// Running LFO with the phase and the delay as fractions of a cycle.
+#[derive(Debug, Clone)]
+pub struct LfoOscillator {
+    waveform: LfoWaveform,
+    frequency: f64,
+    delay_speeds: Option<(f64, f64)>,
+    phase: f64,
+    delay: f64,
+    random: u8,
+    value: f64,
+}
+
+impl LfoOscillator {
+    /// Makes a new oscillator for `lfo` at key on.
+    pub fn new(lfo: &Lfo) -> Self {
+        Self::with_seed(lfo, 0)
+    }
+
+    /// Makes a new oscillator for `lfo` at key on, with `seed` as the
+    /// initial state of the sample-and-hold random number generator.
+    pub fn with_seed(lfo: &Lfo, seed: u8) -> Self {
+        let mut result = Self {
+            waveform: lfo.waveform,
+            frequency: lfo.frequency(),
+            delay_speeds: lfo.delay_speeds(),
+            // With key sync the LFO starts from the middle of the cycle.
+            phase: if lfo.sync { 0.5 } else { 0.0 },
+            delay: 0.0,
+            random: seed,
+            value: 0.0,
+        };
+        result.value = result.waveform.value(result.phase).unwrap_or_else(|| result.random_value());
+        result
+    }
+
+    // Gets the sample-and-hold value for the current random state.
+    fn random_value(&self) -> f64 {
+        ((self.random ^ 0x80) as f64 + 1.0) / 256.0
+    }
+
+    /// Advances the LFO by `seconds` and gets its value 0.0...1.0.
+    pub fn advance(&mut self, seconds: f64) -> f64 {
+        self.phase += self.frequency * seconds;
+        let wrapped = self.phase >= 1.0;
+        self.phase = self.phase.fract();
+        self.value = match self.waveform.value(self.phase) {
+            Some(value) => value,
+            None => {
+                // A new random value is picked at the start of each cycle.
+                if wrapped {
+                    self.random = self.random.wrapping_mul(179).wrapping_add(17);
+                }
+                self.random_value()
+            },
+        };
+        self.value
+    }
+
+    /// Advances the delay by `seconds` and gets the LFO depth 0.0...1.0.
+    pub fn advance_delay(&mut self, seconds: f64) -> f64 {
+        let Some((first, second)) = self.delay_speeds else {
+            return 1.0;
+        };
+        let speed = if self.delay < 0.5 { first } else { second };
+        self.delay = (self.delay + speed * seconds).min(1.0);
+        if self.delay < 0.5 { 0.0 } else { (self.delay - 0.5) * 2.0 }
+    }
+
+    /// Gets the current value 0.0...1.0.
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+}
+
+impl Voice {
+    /// Describes the LFO of the voice like "5.2 Hz triangle, ±12 cents vibrato",
+    /// using the largest amplitude modulation sensitivity of the operators
+    /// for the tremolo depth.
+    pub fn lfo_description(&self) -> String {
+        let mut parts = vec![format!("{:.1} Hz {}", self.lfo.frequency(), self.lfo.waveform)];
+        if self.lfo.delay.value() > 0 {
+            parts.push(format!("{:.2} s delay", self.lfo.delay_time()));
+        }
+        let vibrato = self.lfo.vibrato_depth(self.pitch_mod_sens);
+        if vibrato > 0.0 {
+            parts.push(format!("±{:.0} cents vibrato", vibrato));
+        }
+        let sensitivity = self.operators.iter().map(|op| op.amp_mod_sens).max_by_key(|s| s.value());
+        let tremolo = sensitivity.map_or(0.0, |s| self.lfo.tremolo_depth(s));
+        if tremolo > 0.0 {
+            parts.push(format!("{:.1} dB tremolo", tremolo));
+        }
+        parts.join(", ")
+    }
+}
+
+impl Default for Lfo {
+    fn default() -> Self {
+        Lfo::new()
+    }
+}
+
 impl fmt::Display for Lfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "speed = {}, delay = {}, PMD = {}, AMD = {}, sync = {}, waveform = {:?}",
@@ -99,28 +330,28 @@ impl fmt::Display for Lfo {
     }
 }
 
-impl SystemExclusiveData for Lfo {
-    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+impl Lfo {
+    /// Makes an LFO from SysEx bytes, handling out-of-range values
+    /// according to the context policy.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, Self::data_size(), "lfo")?;
+
         Ok(Lfo {
-            speed: Level::new(data[0].into()),
-            delay: Level::new(data[1].into()),
-            pmd: Level::new(data[2].into()),
-            amd: Level::new(data[3].into()),
-            sync: if data[4] == 1 { true } else { false },
-            waveform: match data[5] {
-                0 => LfoWaveform::Triangle,
-                1 => LfoWaveform::SawDown,
-                2 => LfoWaveform::SawUp,
-                3 => LfoWaveform::Square,
-                4 => LfoWaveform::Sine,
-                5 => LfoWaveform::SampleAndHold,
-                _ => {
-                    warn!("LFO waveform out of range: {}, setting to TRI", data[5]);
-                    LfoWaveform::Triangle
-                }
-            },
+            speed: context.ranged::<Level>(data, 0, "speed")?,
+            delay: context.ranged::<Level>(data, 1, "delay")?,
+            pmd: context.ranged::<Level>(data, 2, "pmd")?,
+            amd: context.ranged::<Level>(data, 3, "amd")?,
+            sync: context.switch(data, 4, "sync")?,
+            waveform: context.choice(data, 5, "waveform",
+                LfoWaveform::try_from, LfoWaveform::Triangle)?,
         })
     }
+}
+
+impl SystemExclusiveData for Lfo {
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
 
     fn to_bytes(&self) -> Vec<u8> {
         vec![
@@ -133,5 +364,50 @@ impl SystemExclusiveData for Lfo {
         ]
     }
 
-    fn data_size() -> usize { 7 }
+    fn data_size() -> usize { 6 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_and_delay() {
+        let lfo = Lfo { speed: Level::new(35), ..Lfo::new() };
+        assert!((lfo.frequency() - 5.81).abs() < 0.01, "frequency = {}", lfo.frequency());
+        assert!(Lfo { speed: Level::new(99), ..lfo }.frequency() > 20.0);
+        assert_eq!(lfo.delay_time(), 0.0);
+
+        let delayed = Lfo { delay: Level::new(50), ..lfo };
+        assert!(delayed.delay_time() > 0.1 && delayed.delay_time() < 2.0);
+        assert!(Lfo { delay: Level::new(80), ..lfo }.delay_time() > delayed.delay_time());
+    }
+
+    #[test]
+    fn test_waveforms() {
+        assert_eq!(LfoWaveform::Triangle.value(0.25), Some(0.5));
+        assert_eq!(LfoWaveform::Square.value(0.75), Some(0.0));
+        assert_eq!(LfoWaveform::SampleAndHold.value(0.0), None);
+
+        let lfo = Lfo { waveform: LfoWaveform::SampleAndHold, speed: Level::new(99), ..Lfo::new() };
+        let values = |seed| {
+            let mut oscillator = LfoOscillator::with_seed(&lfo, seed);
+            (0..100).map(|_| oscillator.advance(0.01)).collect::<Vec<_>>()
+        };
+        assert_eq!(values(1), values(1));
+        assert_ne!(values(1), values(2));
+    }
+
+    #[test]
+    fn test_modulation_depth() {
+        let lfo = Lfo { pmd: Level::new(99), amd: Level::new(99), ..Lfo::new() };
+        assert_eq!(lfo.vibrato_depth(Depth::new(0)), 0.0);
+        let vibrato = lfo.vibrato_depth(Depth::new(7));
+        assert!(vibrato > 1000.0 && vibrato < 1200.0, "vibrato = {}", vibrato);
+        assert_eq!(lfo.tremolo_depth(Sensitivity::new(0)), 0.0);
+        assert!(lfo.tremolo_depth(Sensitivity::new(3)) > lfo.tremolo_depth(Sensitivity::new(1)));
+
+        let voice = Voice { lfo: Lfo { pmd: Level::new(20), ..Lfo::new() }, pitch_mod_sens: Depth::new(3), ..Voice::new() };
+        assert_eq!(voice.lfo_description(), "5.8 Hz triangle, ±31 cents vibrato");
+    }
 }
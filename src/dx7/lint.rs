@@ -0,0 +1,239 @@
+use std::fmt;
+
+use syxpack::Ranged;
+
+use crate::dx7::{
+    Depth,
+    Note,
+};
+use crate::dx7::cartridge::Cartridge;
+use crate::dx7::operator::OperatorMode;
+use crate::dx7::parameter::{
+    OperatorParameter,
+    VoiceParameter,
+};
+use crate::dx7::policy::Repair;
+use crate::dx7::voice::Voice;
+
+/// Lowest and highest MIDI notes of the 61-key DX7 keyboard (C1...C6).
+pub const KEYBOARD_RANGE: (i32, i32) = (36, 96);
+
+/// Lowest and highest MIDI notes that a transposed keyboard is expected
+/// to play, the range of an 88-key piano (A-1...C7).
+pub const PLAYABLE_RANGE: (i32, i32) = (21, 108);
+
+/// How serious a lint finding is.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    /// Unusual, but often intended.
+    Info,
+
+    /// Probably a mistake.
+    Warning,
+
+    /// The voice cannot work as intended.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A likely mistake in a voice, with the path of the parameter
+/// it concerns (like `op3.eg.levels[3]`) and an explanation.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub parameter: String,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(severity: Severity, parameter: &str, message: String) -> Self {
+        Finding { severity, parameter: parameter.to_string(), message }
+    }
+
+    // Makes a finding about a voice parameter, with the same path
+    // as in voice diffs and parameter change messages.
+    fn about(severity: Severity, parameter: VoiceParameter, message: String) -> Self {
+        Finding::new(severity, &parameter.path(), message)
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.parameter, self.message)
+    }
+}
+
+impl Voice {
+    /// Checks the voice for likely mistakes.
+    pub fn lint(&self) -> Vec<Finding> {
+        self.lint_with_repairs(&[])
+    }
+
+    /// Checks the voice for likely mistakes, also reporting the `repairs`
+    /// made when the voice was parsed leniently.
+    pub fn lint_with_repairs(&self, repairs: &[Repair]) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let topology = self.alg.topology();
+
+        if topology.carriers.iter().all(|&op| self.operators[op - 1].output_level.value() == 0) {
+            findings.push(Finding::about(Severity::Error, VoiceParameter::Algorithm,
+                format!("all carriers of algorithm {} have output level 0, so the voice is silent", self.alg)));
+        }
+
+        for &op in &topology.carriers {
+            let operator = &self.operators[op - 1];
+            if operator.output_level.value() == 0 {
+                continue;
+            }
+            let level = operator.eg.levels[3].value();
+            if level != 0 {
+                findings.push(Finding::about(Severity::Warning, VoiceParameter::Operator(op, OperatorParameter::EgLevel(3)),
+                    format!("carrier OP{} has envelope level 4 at {}, so notes never fade out after release", op, level)));
+            }
+            if matches!(operator.mode, OperatorMode::Fixed) {
+                findings.push(Finding::about(Severity::Info, VoiceParameter::Operator(op, OperatorParameter::Mode),
+                    format!("carrier OP{} has a fixed frequency, so its pitch does not follow the keyboard", op)));
+            }
+        }
+
+        let feedback_carrier = topology.feedback_operators().into_iter().find(|&op| topology.is_carrier(op));
+        if let Some(op) = feedback_carrier && self.feedback.value() == Depth::LAST {
+            findings.push(Finding::about(Severity::Warning, VoiceParameter::Feedback,
+                format!("maximum feedback on carrier OP{} turns its output into noise", op)));
+        }
+
+        let (low, high) = KEYBOARD_RANGE;
+        let (first, last) = PLAYABLE_RANGE;
+        let transpose = self.transpose.value();
+        if low + transpose < first || high + transpose > last {
+            findings.push(Finding::about(Severity::Warning, VoiceParameter::Transpose,
+                format!("transpose {} moves the keyboard outside the playable note range {}...{}", transpose,
+                    Note::new(first).name(), Note::new(last).name())));
+        }
+
+        for (i, c) in self.name.value().chars().enumerate() {
+            if !(c.is_ascii_graphic() || c == ' ') {
+                findings.push(Finding::about(Severity::Warning, VoiceParameter::NameChar(i),
+                    format!("character {:?} cannot be shown on the DX7 display", c)));
+            }
+        }
+
+        for repair in repairs {
+            findings.push(Finding::new(Severity::Warning, &repair.parameter,
+                format!("raw value {} was out of range and was replaced with {}", repair.raw, repair.value)));
+        }
+
+        findings
+    }
+}
+
+impl Cartridge {
+    /// Checks every voice in the cartridge for likely mistakes, using the
+    /// `repairs` made when the cartridge was parsed. Returns the findings
+    /// as pairs of voice index and finding.
+    pub fn lint_with_repairs(&self, repairs: &[Repair]) -> Vec<(usize, Finding)> {
+        self.voices.iter().enumerate().flat_map(|(index, voice)| {
+            let voice_repairs: Vec<Repair> = repairs.iter()
+                .filter(|repair| repair.voice == Some(index))
+                .cloned()
+                .collect();
+            voice.lint_with_repairs(&voice_repairs).into_iter().map(move |finding| (index, finding))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syxpack::SystemExclusiveData;
+    use crate::dx7::{
+        Algorithm,
+        Level,
+        Transpose,
+        rom1a,
+    };
+    use crate::dx7::policy::ParseContext;
+    use crate::dx7::voice::VoiceName;
+
+    fn parameters(findings: &[Finding]) -> Vec<&str> {
+        findings.iter().map(|finding| finding.parameter.as_str()).collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        let findings = Voice::new().lint();
+        assert_eq!(parameters(&findings), vec!["alg"]);
+        assert_eq!(findings[0].severity, Severity::Error);
+
+        // Algorithm 32 has OP6 as a carrier with feedback.
+        let mut voice = Voice::new();
+        voice.alg = Algorithm::new(32);
+        voice.operators[5].output_level = Level::new(99);
+        voice.operators[5].eg.levels[3] = Level::new(20);
+        voice.operators[5].mode = OperatorMode::Fixed;
+        voice.feedback = Depth::new(7);
+        voice.transpose = Transpose::new(-24);
+        voice.name = VoiceName::new("BAD\tNAME");
+        let findings = voice.lint();
+        assert_eq!(parameters(&findings),
+            vec!["op6.eg.levels[3]", "op6.mode", "feedback", "transpose", "name[3]"]);
+        assert_eq!(findings[1].to_string(),
+            "info: op6.mode: carrier OP6 has a fixed frequency, so its pitch does not follow the keyboard");
+
+        // In algorithms 4 and 6 the feedback loop spans two or three
+        // operators, and only the lowest one in the loop is a carrier.
+        for (alg, carrier) in [(4, 4), (6, 5)] {
+            let mut voice = Voice::new();
+            voice.alg = Algorithm::new(alg);
+            voice.operators[0].output_level = Level::new(99);
+            voice.feedback = Depth::new(7);
+            let findings = voice.lint();
+            assert_eq!(parameters(&findings), vec!["feedback"], "algorithm {}", alg);
+            assert_eq!(findings[0].message,
+                format!("maximum feedback on carrier OP{} turns its output into noise", carrier));
+        }
+    }
+
+    #[test]
+    fn test_transpose_range() {
+        let transposed = |semitones| {
+            let voice = Voice { transpose: Transpose::new(semitones), ..Voice::new() };
+            voice.lint().into_iter().filter(|finding| finding.parameter == "transpose").count()
+        };
+        assert_eq!(transposed(-24), 1);
+        assert_eq!(transposed(24), 1);
+        assert_eq!(transposed(-12), 0);
+        assert_eq!(transposed(12), 0);
+
+        let voice = Voice { transpose: Transpose::new(24), ..Voice::new() };
+        let finding = voice.lint().into_iter().find(|finding| finding.parameter == "transpose").unwrap();
+        assert_eq!(finding.message, "transpose 24 moves the keyboard outside the playable note range A-1...C7");
+    }
+
+    #[test]
+    fn test_lint_with_repairs() {
+        let cartridge = rom1a();
+        assert!(cartridge.voices[0].lint().iter().all(|finding| finding.severity != Severity::Error));
+
+        let mut data = Cartridge::default().to_bytes();
+        data[118 + 4] = 0xff;  // a name character in the first voice
+        let mut context = ParseContext::lenient();
+        let cartridge = Cartridge::parse_with(&data, &mut context).unwrap();
+        let findings = cartridge.lint_with_repairs(&context.repairs);
+        let repaired: Vec<&(usize, Finding)> = findings.iter()
+            .filter(|(_, finding)| finding.parameter == "name[4]")
+            .collect();
+        assert_eq!(repaired.len(), 1);
+        assert_eq!(repaired[0].0, 0);
+        assert_eq!(repaired[0].1.message, "raw value 255 was out of range and was replaced with 32");
+    }
+}
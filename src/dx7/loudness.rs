@@ -0,0 +1,136 @@
+use syxpack::Ranged;
+
+use crate::dx7::{
+    Note,
+    Velocity,
+};
+use crate::dx7::envelope::{
+    amplitude,
+    stage_level,
+};
+use crate::dx7::operator::Operator;
+use crate::dx7::voice::Voice;
+
+/// Loudness in decibels below which a voice counts as silent.
+pub const SILENCE_THRESHOLD: f64 = -60.0;
+
+static VELOCITY_TABLE: [i32; 64] = [
+    0, 70, 86, 97, 106, 114, 121, 126, 132, 138, 142, 148, 152, 156, 160, 163,
+    166, 170, 173, 174, 178, 181, 184, 186, 189, 190, 194, 196, 198, 200, 202,
+    205, 206, 209, 211, 214, 216, 218, 220, 222, 224, 225, 227, 229, 230, 232,
+    233, 235, 237, 238, 240, 241, 242, 243, 244, 246, 246, 248, 249, 250, 251,
+    252, 253, 254
+];
+
+// Converts an amplitude to decibels.
+fn decibels(amplitude: f64) -> f64 {
+    20.0 * amplitude.log10()
+}
+
+impl Operator {
+    /// Gets the change in envelope level caused by `velocity`, in internal
+    /// envelope units of 1/256 octave. Soft notes are attenuated, and with
+    /// high sensitivity the hardest notes get a small boost.
+    pub fn velocity_offset(&self, velocity: Velocity) -> i32 {
+        let value = VELOCITY_TABLE[(velocity.value() >> 1) as usize] - 239;
+        ((self.key_vel_sens.value() * value + 7) >> 3) << 4
+    }
+
+    /// Gets the output level of the operator for `note` and `velocity`,
+    /// with keyboard level scaling and velocity sensitivity applied,
+    /// in the internal range 0...4064 used by the envelope.
+    pub fn scaled_output_level(&self, note: Note, velocity: Velocity) -> i32 {
+        ((self.effective_output_level(note) << 5) + self.velocity_offset(velocity)).max(0)
+    }
+
+    /// Gets the peak loudness of the operator for `note` and `velocity`
+    /// in decibels relative to full output. The attenuation from amplitude
+    /// modulation is `amp_mod`, as a fraction of the envelope level.
+    /// Silent operators get negative infinity.
+    pub fn loudness(&self, note: Note, velocity: Velocity, amp_mod: f64) -> f64 {
+        let output_level = self.scaled_output_level(note, velocity);
+        let peak = self.eg.levels.iter()
+            .map(|level| stage_level(level.value(), output_level))
+            .fold(0.0, f64::max);
+        decibels(amplitude(peak * (1.0 - amp_mod)))
+    }
+}
+
+impl Voice {
+    /// Gets the peak loudness of each carrier for `note` and `velocity`
+    /// in decibels, as pairs of operator number and loudness. Amplitude
+    /// modulation is taken with the LFO in the middle of its range.
+    pub fn carrier_loudness(&self, note: Note, velocity: Velocity) -> Vec<(usize, f64)> {
+        self.alg.topology().carriers.iter().map(|&number| {
+            let op = &self.operators[number - 1];
+            let amp_mod = self.lfo.amplitude_modulation(op.amp_mod_sens, 0.5, 1.0);
+            (number, op.loudness(note, velocity, amp_mod))
+        }).collect()
+    }
+
+    /// Gets the loudness of the voice for `note` and `velocity` in decibels,
+    /// as the power sum of the carriers mixed with equal weights.
+    pub fn loudness(&self, note: Note, velocity: Velocity) -> f64 {
+        let carriers = self.carrier_loudness(note, velocity);
+        let power: f64 = carriers.iter().map(|(_, db)| 10.0_f64.powf(db / 10.0)).sum();
+        decibels((power / (carriers.len() * carriers.len()) as f64).sqrt())
+    }
+
+    /// Returns `true` if the voice is below the silence threshold
+    /// for `note` and `velocity`.
+    pub fn is_silent(&self, note: Note, velocity: Velocity) -> bool {
+        self.loudness(note, velocity) < SILENCE_THRESHOLD
+    }
+
+    /// Gets the lowest velocity at which `note` is audible with this voice,
+    /// or `None` if it is silent at every velocity. Voices whose lowest
+    /// audible velocity is high are hard to play softly.
+    pub fn lowest_audible_velocity(&self, note: Note) -> Option<Velocity> {
+        (Velocity::FIRST..=Velocity::LAST)
+            .map(Velocity::new)
+            .find(|&velocity| !self.is_silent(note, velocity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dx7::{
+        Algorithm,
+        Depth,
+        Level,
+    };
+
+    fn sine_voice(sensitivity: i32) -> Voice {
+        let mut voice = Voice::new();
+        voice.alg = Algorithm::new(32);
+        voice.operators[0].output_level = Level::new(99);
+        voice.operators[0].key_vel_sens = Depth::new(sensitivity);
+        voice
+    }
+
+    #[test]
+    fn test_velocity_offset() {
+        let op = sine_voice(7).operators[0];
+        assert!(op.velocity_offset(Velocity::new(0)) < op.velocity_offset(Velocity::new(64)));
+        assert!(op.velocity_offset(Velocity::new(127)) > 0);
+        assert_eq!(sine_voice(0).operators[0].velocity_offset(Velocity::new(0)), 0);
+    }
+
+    #[test]
+    fn test_loudness() {
+        let note = Note::new(60);
+        let voice = sine_voice(0);
+        assert_eq!(voice.carrier_loudness(note, Velocity::new(100))[0], (1, 0.0));
+        let loudness = voice.loudness(note, Velocity::new(100));
+        assert!((loudness - decibels(1.0 / 6.0)).abs() < 1e-9);
+        assert_eq!(voice.lowest_audible_velocity(note), Some(Velocity::new(0)));
+
+        let voice = Voice { operators: [Operator { output_level: Level::new(60), ..voice.operators[0] }; 6], ..sine_voice(0) };
+        let sensitive = Voice { operators: [Operator { key_vel_sens: Depth::new(7), ..voice.operators[0] }; 6], ..voice.clone() };
+        assert!(sensitive.loudness(note, Velocity::new(10)) < voice.loudness(note, Velocity::new(10)));
+        assert!(sensitive.is_silent(note, Velocity::new(1)));
+        assert!(sensitive.lowest_audible_velocity(note).unwrap().value() > 1);
+        assert!(Voice::new().lowest_audible_velocity(note).is_none());
+    }
+}
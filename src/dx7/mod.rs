@@ -1,3 +1,7 @@
+// The `ranged_impl!` macro from SyxPack checks ranges with comparisons
+// instead of `RangeInclusive::contains`.
+#![allow(clippy::manual_range_contains)]
+
 use std::fmt;
 
 use rand::Rng;
@@ -5,6 +9,7 @@ use syxpack::{
     Ranged,
     ranged_impl,
     Encoding,
+    ParseError,
 };
 
 pub mod voice;
@@ -13,6 +18,24 @@ pub mod operator;
 pub mod lfo;
 pub mod envelope;
 pub mod sysex;
+pub mod policy;
+pub mod parameter;
+pub mod function;
+pub mod supplement;
+pub mod performance;
+pub mod tx802;
+pub mod tuning;
+pub mod algorithm;
+pub mod render;
+pub mod wav;
+pub mod loudness;
+pub mod text;
+pub mod diff;
+pub mod canonical;
+pub mod fingerprint;
+pub mod lint;
+#[cfg(feature = "serde")]
+pub mod serialization;
 
 /// Algorithm (1...32)
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -98,6 +121,73 @@ ranged_impl!(Level, 0, 99, 0);
 
 impl Encoding for Level { } // identity mapping, no adjustment needed
 
+/// MIDI note number (0...127)
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Note(i32);
+
+ranged_impl!(Note, 0, 127, 60);
+
+impl Encoding for Note { } // identity mapping, no adjustment needed
+
+impl Note {
+    /// Gets the name of the note, with middle C (60) as C3 like on the DX7.
+    pub fn name(&self) -> String {
+        let notes = [ "C", "C#", "D", "Eb", "E", "F", "F#", "G", "G#", "A", "Bb", "B" ];
+        let octave = self.value() / 12 - 2;
+        let name = notes[(self.value() % 12) as usize];
+        format!("{}{}", name, octave)
+    }
+}
+
+/// MIDI note velocity (0...127)
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Velocity(i32);
+
+ranged_impl!(Velocity, 0, 127, 100);
+
+impl Encoding for Velocity { } // identity mapping, no adjustment needed
+
+/// Checks that there are at least `size` bytes of data for parsing `what`.
+/// Returns an error with the offset of the first missing byte otherwise.
+pub fn check_length(data: &[u8], size: usize, what: &str) -> Result<(), ParseError> {
+    if data.len() < size {
+        return Err(ParseError::InvalidData(
+            data.len() as u32,
+            format!("{}: got {} bytes of data, expected {} bytes", what, data.len(), size)));
+    }
+    Ok(())
+}
+
+/// Makes an error for an invalid value of the field `name` at `offset`.
+pub fn invalid_field(offset: usize, name: &str, reason: &str) -> ParseError {
+    ParseError::InvalidData(offset as u32, format!("{}: {}", name, reason))
+}
+
+/// Adjusts a parse error from a nested data structure so that the offset
+/// is relative to the enclosing structure, and the field name is prefixed
+/// with the name of the nested structure (like `op6.` or `lfo.`).
+pub fn nested_error(error: ParseError, base: usize, prefix: &str) -> ParseError {
+    match error {
+        ParseError::InvalidData(offset, message) => {
+            ParseError::InvalidData(
+                offset + base as u32,
+                if prefix.is_empty() { message } else { format!("{}.{}", prefix, message) })
+        },
+        other => other,
+    }
+}
+
+/// Gets the SysEx bytes of a name, padded with spaces or truncated
+/// to `length` characters. Characters that are not ASCII are written
+/// as spaces.
+pub fn name_bytes(name: &str, length: usize) -> Vec<u8> {
+    name.chars()
+        .map(|c| if c.is_ascii() { c as u8 } else { b' ' })
+        .chain(std::iter::repeat(b' '))
+        .take(length)
+        .collect()
+}
+
 // Finds the first offset where the two slices differ.
 // Returns None if no differences are found, or if the slices
 // are different lengths, Some<usize> with the offset otherwise.
@@ -106,18 +196,21 @@ pub fn compare_slices(v1: &[u8], v2: &[u8]) -> Option<usize> {
         return None;
     }
 
-    let mut offset = 0;
-    for i in 0..v1.len() {
-        if v1[i] != v2[i] {
-            return Some(offset);
-        }
-        offset += 1;
-    }
+    v1.iter().zip(v2.iter()).position(|(a, b)| a != b)
+}
 
-    None
+// Parses the original DX7 ROM1A cartridge, used as a test fixture.
+// The payload starts with the four header bytes.
+#[cfg(test)]
+pub(crate) fn rom1a() -> cartridge::Cartridge {
+    use syxpack::SystemExclusiveData;
+    let data = include_bytes!("rom1a_payload.dat");
+    cartridge::Cartridge::parse(&data[4..4100]).expect("valid ROM1A cartridge")
 }
 
+// The tests are kept next to the helpers above, before the diagrams.
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod tests {
     use bit::BitIndex;
     use syxpack::parse_or_default;
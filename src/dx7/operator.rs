@@ -1,8 +1,8 @@
 use std::fmt;
+use std::str::FromStr;
 use bit::BitIndex;
 use rand::Rng;
 
-use dbg_hex::dbg_hex;
 
 use syxpack::{
     ParseError,
@@ -10,7 +10,6 @@ use syxpack::{
     ranged_impl,
     Encoding,
     SystemExclusiveData,
-    parse_or_default,
 };
 
 use crate::dx7::{
@@ -19,9 +18,16 @@ use crate::dx7::{
     Detune,
     Sensitivity,
     Coarse,
+    Note,
+    check_length,
 };
 
-use crate::dx7::envelope::Envelope;
+use crate::dx7::envelope::{
+    Envelope,
+    scale_output_level,
+};
+use crate::dx7::policy::ParseContext;
+use crate::dx7::tuning::Tuning;
 
 
 /// Scaling curve style.
@@ -80,6 +86,28 @@ impl ScalingCurve {
     pub fn exp_neg() -> Self {
         ScalingCurve { style: CurveStyle::Exponential, sign: CurveSign::Negative }
     }
+
+    /// Gets the short name of the curve, like "lin-" or "exp+",
+    /// used in the text and JSON formats.
+    pub fn name(&self) -> &'static str {
+        match u8::from(*self) {
+            0 => "lin-",
+            1 => "exp-",
+            2 => "exp+",
+            _ => "lin+",
+        }
+    }
+}
+
+impl FromStr for ScalingCurve {
+    type Err = &'static str;
+
+    /// Makes a scaling curve from its short name.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        (0..4u8).map(|code| ScalingCurve::try_from(code).expect("valid curve code"))
+            .find(|curve| curve.name() == name)
+            .ok_or("Bad scaling curve name, expected lin-, exp-, exp+ or lin+")
+    }
 }
 
 impl fmt::Display for ScalingCurve {
@@ -88,21 +116,23 @@ impl fmt::Display for ScalingCurve {
     }
 }
 
-impl From<u8> for ScalingCurve {
-    fn from(item: u8) -> Self {
-        match item {
-            0 => ScalingCurve::lin_neg(),
-            1 => ScalingCurve::exp_neg(),
-            2 => ScalingCurve::exp_pos(),
-            3 => ScalingCurve::lin_pos(),
-            _ => panic!("expected value in range 0...3, got {}", item)
+impl TryFrom<u8> for ScalingCurve {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ScalingCurve::lin_neg()),
+            1 => Ok(ScalingCurve::exp_neg()),
+            2 => Ok(ScalingCurve::exp_pos()),
+            3 => Ok(ScalingCurve::lin_pos()),
+            _ => Err("Bad scaling curve value, expected 0...3")
         }
     }
 }
 
-impl Into<u8> for ScalingCurve {
-    fn into(self) -> u8 {
-        match self {
+impl From<ScalingCurve> for u8 {
+    fn from(curve: ScalingCurve) -> u8 {
+        match curve {
             ScalingCurve { style: CurveStyle::Linear, sign: CurveSign::Positive } => 3,
             ScalingCurve { style: CurveStyle::Linear, sign: CurveSign::Negative } => 0,
             ScalingCurve { style: CurveStyle::Exponential, sign: CurveSign::Positive } => 2,
@@ -119,22 +149,51 @@ ranged_impl!(Key, 0, 99, 39);  // note the default!
 impl Encoding for Key { }  // identity transformation
 
 impl Key {
+    /// Gets the MIDI note of the key. Key 0 is A-1 (note 21).
+    pub fn note(&self) -> Note {
+        Note::new(self.value() + 21)
+    }
+
+    /// Gets the name of the key as shown on the DX7: key 0 is A-1,
+    /// key 39 is C3 (middle C) and key 99 is C8.
     pub fn name(&self) -> String {
-        let notes = [ "C", "C#", "D", "Eb", "E", "F", "F#", "G", "G#", "A", "Bb", "B" ];
-        let octave: usize = self.value() as usize / 12 + 1;
-        let name = notes[(self.value() % 12) as usize];
-        format!("{}{}", name, octave)
+        self.note().name()
     }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Scaling {
     pub depth: Level,
     pub curve: ScalingCurve,
 }
 
+static EXP_SCALE_TABLE: [i32; 33] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 11, 14, 16, 19, 23, 27, 33, 39, 47, 56, 66,
+    80, 94, 110, 126, 142, 158, 174, 190, 206, 222, 238, 250
+];
+
+impl Scaling {
+    // Gets the level offset `group` steps of three keys away from the breakpoint.
+    fn offset(&self, group: i32) -> i32 {
+        let depth = self.depth.value();
+        let scale = match self.curve.style {
+            CurveStyle::Linear => (group * depth * 329) >> 12,
+            CurveStyle::Exponential => {
+                let index = (group as usize).min(EXP_SCALE_TABLE.len() - 1);
+                (EXP_SCALE_TABLE[index] * depth * 329) >> 15
+            },
+        };
+        match self.curve.sign {
+            CurveSign::Negative => -scale,
+            CurveSign::Positive => scale,
+        }
+    }
+}
+
 /// Keyboard level scaling.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KeyboardLevelScaling {
     pub breakpoint: Key, // 0 ~ 99 (A-1 ~ C8)
     pub left: Scaling,
@@ -152,6 +211,26 @@ impl KeyboardLevelScaling {
     }
 }
 
+impl KeyboardLevelScaling {
+    /// Gets the offset that the scaling applies to the output level
+    /// of an operator for `note`, in the internal output level range
+    /// 0...127 (about 0.75 dB per step).
+    pub fn offset(&self, note: Note) -> i32 {
+        let offset = note.value() - self.breakpoint.value() - 17;
+        if offset >= 0 {
+            self.right.offset((offset + 1) / 3)
+        } else {
+            self.left.offset(-(offset - 1) / 3)
+        }
+    }
+}
+
+impl Default for KeyboardLevelScaling {
+    fn default() -> Self {
+        KeyboardLevelScaling::new()
+    }
+}
+
 impl fmt::Display for KeyboardLevelScaling {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "breakpoint = {}, left depth = {}, right depth = {}, left curve = {}, right curve = {}",
@@ -159,21 +238,33 @@ impl fmt::Display for KeyboardLevelScaling {
     }
 }
 
-impl SystemExclusiveData for KeyboardLevelScaling {
-    /// Makes new keyboard level scaling settings from SysEx bytes.
-    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+impl KeyboardLevelScaling {
+    /// Makes new keyboard level scaling settings from SysEx bytes,
+    /// handling out-of-range values according to the context policy.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, Self::data_size(), "keyboard level scaling")?;
+
         Ok(Self {
-            breakpoint: parse_or_default::<Key>(data[0]),
-            left: Scaling { 
-                depth: parse_or_default::<Level>(data[1]), 
-                curve: ScalingCurve::from(data[3])
+            breakpoint: context.ranged::<Key>(data, 0, "breakpoint")?,
+            left: Scaling {
+                depth: context.ranged::<Level>(data, 1, "left.depth")?,
+                curve: context.choice(data, 3, "left.curve",
+                    ScalingCurve::try_from, ScalingCurve::lin_neg())?,
             },
-            right: Scaling { 
-                depth: parse_or_default::<Level>(data[2]), 
-                curve: ScalingCurve::from(data[4])
+            right: Scaling {
+                depth: context.ranged::<Level>(data, 2, "right.depth")?,
+                curve: context.choice(data, 4, "right.curve",
+                    ScalingCurve::try_from, ScalingCurve::lin_neg())?,
             },
         })
     }
+}
+
+impl SystemExclusiveData for KeyboardLevelScaling {
+    /// Makes new keyboard level scaling settings from SysEx bytes.
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
 
     /// Gets the SysEx bytes representing this set of parameters.
     fn to_bytes(&self) -> Vec<u8> {
@@ -191,11 +282,31 @@ impl SystemExclusiveData for KeyboardLevelScaling {
 
 /// Operator mode.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum OperatorMode {
     Ratio,
     Fixed,
 }
 
+impl TryFrom<u8> for OperatorMode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(OperatorMode::Ratio),
+            1 => Ok(OperatorMode::Fixed),
+            _ => Err("Bad operator mode value, expected 0...1")
+        }
+    }
+}
+
+impl From<OperatorMode> for u8 {
+    fn from(mode: OperatorMode) -> u8 {
+        mode as u8
+    }
+}
+
 impl fmt::Display for OperatorMode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let printable = match *self {
@@ -206,13 +317,59 @@ impl fmt::Display for OperatorMode {
     }
 }
 
+/// Frequency of an operator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    /// Multiple of the frequency of the note played.
+    Ratio(f64),
+
+    /// Absolute frequency in hertz.
+    Fixed(f64),
+}
+
+impl Frequency {
+    /// Gets the frequency in hertz when playing a note
+    /// whose frequency is `note_frequency`.
+    pub fn hertz(&self, note_frequency: f64) -> f64 {
+        match self {
+            Frequency::Ratio(ratio) => ratio * note_frequency,
+            Frequency::Fixed(hertz) => *hertz,
+        }
+    }
+}
+
+impl fmt::Display for Frequency {
+    /// Formats the frequency like the DX7 display, with four significant
+    /// digits for fixed frequencies: "1.00", "F 1.000Hz", "F 1000Hz".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Frequency::Ratio(ratio) => write!(f, "{:.2}", ratio),
+            Frequency::Fixed(hertz) => {
+                let decimals = match *hertz {
+                    h if h < 10.0 => 3,
+                    h if h < 100.0 => 2,
+                    h if h < 1000.0 => 1,
+                    _ => 0,
+                };
+                write!(f, "F {:.*}Hz", decimals, hertz)
+            },
+        }
+    }
+}
+
 /// Operator.
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Operator {
+    #[cfg_attr(feature = "serde", serde(rename = "envelope"))]
     pub eg: Envelope,
+    #[cfg_attr(feature = "serde", serde(rename = "level_scaling"))]
     pub kbd_level_scaling: KeyboardLevelScaling,
+    #[cfg_attr(feature = "serde", serde(rename = "rate_scaling"))]
     pub kbd_rate_scaling: Depth, // 0 ~ 7
+    #[cfg_attr(feature = "serde", serde(rename = "amp_mod_sensitivity"))]
     pub amp_mod_sens: Sensitivity,  // 0 ~ 3
+    #[cfg_attr(feature = "serde", serde(rename = "velocity_sensitivity"))]
     pub key_vel_sens: Depth,  // 0 ~ 7
     pub output_level: Level,
     pub mode: OperatorMode,
@@ -312,47 +469,98 @@ impl Operator {
     }
 }
 
-impl SystemExclusiveData for Operator {
-    /// Makes a new operator from SysEx bytes.
-    fn parse(data: &[u8]) -> Result<Self, ParseError> {
-        //dbg!(&data[0..8]);
-        let eg = Envelope::parse(&data[0..8])?;
-        //println!("EG = {}", eg);
+impl Default for Operator {
+    fn default() -> Self {
+        Operator::new()
+    }
+}
 
-        //dbg!(&data[8..13]);
-        let kbd_level_scaling = KeyboardLevelScaling::parse(&data[8..13])?;
-        //println!("KLS = {}", kbd_level_scaling);
+impl Operator {
+    /// Gets the output level of the operator for `note` with keyboard
+    /// level scaling applied, in the internal range up to 127. The level
+    /// can be negative, so that velocity sensitivity applies to it before
+    /// it is limited to zero in `scaled_output_level`.
+    pub fn effective_output_level(&self, note: Note) -> i32 {
+        let level = scale_output_level(self.output_level.value());
+        (level + self.kbd_level_scaling.offset(note)).min(127)
+    }
 
-        //dbg!(data[13]);
-        let kbd_rate_scaling = parse_or_default::<Depth>(data[13]);
-        //dbg!(kbd_rate_scaling);
+    /// Gets the frequency of the operator when playing `note`
+    /// in equal temperament, including detune.
+    pub fn frequency(&self, note: Note) -> Frequency {
+        self.frequency_at(Tuning::equal_temperament().frequency(note))
+    }
 
-        //dbg!(data[14]);
-        let amp_mod_sens = parse_or_default::<Sensitivity>(data[14]);
-        //dbg!(amp_mod_sens);
+    /// Gets the frequency of the operator when playing a note whose
+    /// frequency is `note_frequency`, including detune.
+    pub fn frequency_at(&self, note_frequency: f64) -> Frequency {
+        let detune = 2.0_f64.powf(self.detune_cents(note_frequency) / 1200.0);
+        let coarse = self.coarse.value();
+        let fine = self.fine.value() as f64;
+        match self.mode {
+            OperatorMode::Ratio => {
+                let coarse = if coarse == 0 { 0.5 } else { coarse as f64 };
+                Frequency::Ratio(coarse * (1.0 + fine / 100.0) * detune)
+            },
+            OperatorMode::Fixed => {
+                // Coarse selects the decade (1, 10, 100 or 1000 Hz),
+                // fine goes up in logarithmic steps within it.
+                let exponent = (coarse & 3) as f64 + fine / 100.0;
+                Frequency::Fixed(10.0_f64.powf(exponent) * detune)
+            },
+        }
+    }
 
-        let key_vel_sens = parse_or_default::<Depth>(data[15]);
-        let output_level = parse_or_default::<Level>(data[16]);
-        let mode = if data[17] == 0b1 { OperatorMode::Fixed } else { OperatorMode::Ratio };
-        let coarse = parse_or_default::<Coarse>(data[18]);
-        let fine = parse_or_default::<Level>(data[19]);
+    /// Gets the detune in cents when playing a note whose frequency
+    /// is `note_frequency`. In ratio mode the detune gets narrower
+    /// for higher notes.
+    pub fn detune_cents(&self, note_frequency: f64) -> f64 {
+        let detune = self.detune.value() as f64;
+        match self.mode {
+            OperatorMode::Ratio => {
+                let log_frequency = note_frequency.log2();
+                1200.0 * 0.0209 * (-0.396 * log_frequency).exp() / 7.0 * log_frequency * detune
+            },
+            // Like the original, fixed frequencies are only detuned upwards.
+            OperatorMode::Fixed => {
+                if detune > 0.0 { 1200.0 * 13457.0 * detune / (1 << 24) as f64 } else { 0.0 }
+            },
+        }
+    }
+}
 
-        //dbg!(data[20]);
-        let detune = parse_or_default::<Detune>(data[20]);
+impl Operator {
+    /// Makes a new operator from SysEx bytes, handling out-of-range values
+    /// according to the context policy.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, Self::data_size(), "operator")?;
+
+        let eg = context.nested("eg", 0,
+            |c| Envelope::parse_with(&data[0..8], c))?;
+        let kbd_level_scaling = context.nested("kbd_level_scaling", 8,
+            |c| KeyboardLevelScaling::parse_with(&data[8..13], c))?;
 
         Ok(Self {
             eg,
             kbd_level_scaling,
-            kbd_rate_scaling,
-            amp_mod_sens,
-            key_vel_sens,
-            output_level,
-            mode,
-            coarse,
-            fine,
-            detune,
+            kbd_rate_scaling: context.ranged::<Depth>(data, 13, "kbd_rate_scaling")?,
+            amp_mod_sens: context.ranged::<Sensitivity>(data, 14, "amp_mod_sens")?,
+            key_vel_sens: context.ranged::<Depth>(data, 15, "key_vel_sens")?,
+            output_level: context.ranged::<Level>(data, 16, "output_level")?,
+            mode: context.choice(data, 17, "mode",
+                OperatorMode::try_from, OperatorMode::Ratio)?,
+            coarse: context.ranged::<Coarse>(data, 18, "coarse")?,
+            fine: context.ranged::<Level>(data, 19, "fine")?,
+            detune: context.ranged::<Detune>(data, 20, "detune")?,
         })
     }
+}
+
+impl SystemExclusiveData for Operator {
+    /// Makes a new operator from SysEx bytes.
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
 
     /// Gets the SysEx bytes representing the operator.
     fn to_bytes(&self) -> Vec<u8> {
@@ -436,6 +644,24 @@ mod tests {
         _ = Operator::parse(&op_data).expect("valid operator");
     }
 
+    #[test]
+    fn test_key_name() {
+        assert_eq!(Key::new(0).name(), "A-1");
+        assert_eq!(Key::new(3).name(), "C0");
+        assert_eq!(Key::new(39).name(), "C3");
+        assert_eq!(Key::new(99).name(), "C8");
+    }
+
+    #[test]
+    fn test_curve_name() {
+        for code in 0..4u8 {
+            let curve = ScalingCurve::try_from(code).unwrap();
+            assert_eq!(curve.name().parse::<ScalingCurve>(), Ok(curve));
+        }
+        assert_eq!(ScalingCurve::exp_pos().name(), "exp+");
+        assert!("LIN+".parse::<ScalingCurve>().is_err());
+    }
+
     #[test]
     fn test_pack() {
         let op = Operator {
@@ -481,4 +707,47 @@ mod tests {
 
         assert_eq!(data, expected_data);
     }
+
+    #[test]
+    fn test_frequency() {
+        let mut op = Operator::new();
+        assert_eq!(op.frequency(Note::new(69)), Frequency::Ratio(1.0));
+        assert_eq!(op.frequency(Note::new(69)).hertz(440.0), 440.0);
+
+        op.coarse = Coarse::new(0);
+        op.fine = Level::new(50);
+        assert_eq!(op.frequency(Note::new(60)).to_string(), "0.75");
+
+        op.detune = Detune::new(7);
+        let low = op.detune_cents(Tuning::equal_temperament().frequency(Note::new(36)));
+        let high = op.detune_cents(Tuning::equal_temperament().frequency(Note::new(96)));
+        assert!(low > high && high > 0.0);
+
+        op = Operator { mode: OperatorMode::Fixed, coarse: Coarse::new(3), ..Operator::new() };
+        assert_eq!(op.frequency(Note::new(60)), Frequency::Fixed(1000.0));
+        assert_eq!(op.frequency(Note::new(60)).to_string(), "F 1000Hz");
+        op.coarse = Coarse::new(4);  // wraps around to 1 Hz
+        assert_eq!(op.frequency(Note::new(60)).to_string(), "F 1.000Hz");
+    }
+
+    #[test]
+    fn test_level_scaling_offset() {
+        let mut scaling = KeyboardLevelScaling::new();
+        scaling.left = Scaling { depth: Level::new(99), curve: ScalingCurve::exp_neg() };
+        scaling.right = Scaling { depth: Level::new(50), curve: ScalingCurve::lin_pos() };
+        let center = scaling.breakpoint.value() + 17;
+        assert_eq!(scaling.offset(Note::new(center)), 0);
+        assert!(scaling.offset(Note::new(center + 12)) > 0);
+        assert!(scaling.offset(Note::new(center + 24)) > scaling.offset(Note::new(center + 12)));
+        assert!(scaling.offset(Note::new(center - 24)) < 0);
+
+        let op = Operator { output_level: Level::new(99), kbd_level_scaling: scaling, ..Operator::new() };
+        assert_eq!(op.effective_output_level(Note::new(center)), 127);
+        assert_eq!(op.effective_output_level(Note::new(127)), 127);
+        assert_eq!(op.effective_output_level(Note::new(0)), 127 + scaling.offset(Note::new(0)));
+
+        let quiet = Operator { output_level: Level::new(0), ..op };
+        assert_eq!(quiet.effective_output_level(Note::new(0)), scaling.offset(Note::new(0)));
+        assert!(quiet.effective_output_level(Note::new(0)) < 0);
+    }
 }
@@ -0,0 +1,579 @@
+use std::fmt;
+
+use syxpack::{
+    Ranged,
+    ParseError,
+    MidiChannel,
+    Encoding,
+    SystemExclusiveData,
+    INITIATOR,
+    TERMINATOR,
+};
+
+use crate::dx7::{
+    check_length,
+    invalid_field,
+};
+use crate::dx7::sysex::YAMAHA;
+use crate::dx7::voice::{
+    Voice,
+    OPERATOR_COUNT,
+    OPERATOR_SIZE,
+    VOICE_NAME_LENGTH,
+    VOICE_SIZE,
+};
+use crate::dx7::policy::ParseContext;
+use crate::dx7::function::{
+    Function,
+    FUNCTION_SIZE,
+};
+
+/// Parameter group of the voice parameters.
+pub const VOICE_GROUP: u8 = 0;
+
+/// Parameter group of the function parameters.
+pub const FUNCTION_GROUP: u8 = 2;
+
+/// Number of the first function parameter.
+pub const FIRST_FUNCTION_PARAMETER: u16 = 64;
+
+/// Sub-status of parameter change messages.
+pub const PARAMETER_SUB_STATUS: u8 = 1;
+
+/// Operator parameter, in the order of the operator SysEx data.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum OperatorParameter {
+    EgRate(usize),  // 0...3
+    EgLevel(usize),  // 0...3
+    Breakpoint,
+    LeftDepth,
+    RightDepth,
+    LeftCurve,
+    RightCurve,
+    KbdRateScaling,
+    AmpModSens,
+    KeyVelSens,
+    OutputLevel,
+    Mode,
+    Coarse,
+    Fine,
+    Detune,
+}
+
+impl OperatorParameter {
+    /// Gets the offset of this parameter in the operator data.
+    pub fn offset(&self) -> usize {
+        match *self {
+            OperatorParameter::EgRate(i) => i,
+            OperatorParameter::EgLevel(i) => 4 + i,
+            OperatorParameter::Breakpoint => 8,
+            OperatorParameter::LeftDepth => 9,
+            OperatorParameter::RightDepth => 10,
+            OperatorParameter::LeftCurve => 11,
+            OperatorParameter::RightCurve => 12,
+            OperatorParameter::KbdRateScaling => 13,
+            OperatorParameter::AmpModSens => 14,
+            OperatorParameter::KeyVelSens => 15,
+            OperatorParameter::OutputLevel => 16,
+            OperatorParameter::Mode => 17,
+            OperatorParameter::Coarse => 18,
+            OperatorParameter::Fine => 19,
+            OperatorParameter::Detune => 20,
+        }
+    }
+
+    /// Gets the operator parameter at `offset` in the operator data.
+    pub fn from_offset(offset: usize) -> Option<Self> {
+        Some(match offset {
+            0..=3 => OperatorParameter::EgRate(offset),
+            4..=7 => OperatorParameter::EgLevel(offset - 4),
+            8 => OperatorParameter::Breakpoint,
+            9 => OperatorParameter::LeftDepth,
+            10 => OperatorParameter::RightDepth,
+            11 => OperatorParameter::LeftCurve,
+            12 => OperatorParameter::RightCurve,
+            13 => OperatorParameter::KbdRateScaling,
+            14 => OperatorParameter::AmpModSens,
+            15 => OperatorParameter::KeyVelSens,
+            16 => OperatorParameter::OutputLevel,
+            17 => OperatorParameter::Mode,
+            18 => OperatorParameter::Coarse,
+            19 => OperatorParameter::Fine,
+            20 => OperatorParameter::Detune,
+            _ => return None,
+        })
+    }
+
+    /// Gets the largest value of this parameter in the SysEx data.
+    pub fn max_value(&self) -> u8 {
+        match *self {
+            OperatorParameter::LeftCurve | OperatorParameter::RightCurve => 3,
+            OperatorParameter::KbdRateScaling | OperatorParameter::KeyVelSens => 7,
+            OperatorParameter::AmpModSens => 3,
+            OperatorParameter::Mode => 1,
+            OperatorParameter::Coarse => 31,
+            OperatorParameter::Detune => 14,
+            _ => 99,
+        }
+    }
+
+    /// Gets the path of this parameter in an operator, like `eg.rates[1]`.
+    pub fn path(&self) -> String {
+        match *self {
+            OperatorParameter::EgRate(i) => format!("eg.rates[{}]", i),
+            OperatorParameter::EgLevel(i) => format!("eg.levels[{}]", i),
+            OperatorParameter::Breakpoint => "kbd_level_scaling.breakpoint".to_string(),
+            OperatorParameter::LeftDepth => "kbd_level_scaling.left.depth".to_string(),
+            OperatorParameter::RightDepth => "kbd_level_scaling.right.depth".to_string(),
+            OperatorParameter::LeftCurve => "kbd_level_scaling.left.curve".to_string(),
+            OperatorParameter::RightCurve => "kbd_level_scaling.right.curve".to_string(),
+            OperatorParameter::KbdRateScaling => "kbd_rate_scaling".to_string(),
+            OperatorParameter::AmpModSens => "amp_mod_sens".to_string(),
+            OperatorParameter::KeyVelSens => "key_vel_sens".to_string(),
+            OperatorParameter::OutputLevel => "output_level".to_string(),
+            OperatorParameter::Mode => "mode".to_string(),
+            OperatorParameter::Coarse => "coarse".to_string(),
+            OperatorParameter::Fine => "fine".to_string(),
+            OperatorParameter::Detune => "detune".to_string(),
+        }
+    }
+}
+
+/// Voice parameter. The parameter numbers are the same as the offsets
+/// in the single voice SysEx data.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum VoiceParameter {
+    Operator(usize, OperatorParameter),  // operator number 1...6
+    PegRate(usize),  // 0...3
+    PegLevel(usize),  // 0...3
+    Algorithm,
+    Feedback,
+    OscSync,
+    LfoSpeed,
+    LfoDelay,
+    LfoPmd,
+    LfoAmd,
+    LfoSync,
+    LfoWaveform,
+    PitchModSens,
+    Transpose,
+    NameChar(usize),  // 0...9
+}
+
+impl VoiceParameter {
+    /// Gets all the voice parameters in parameter number order.
+    pub fn all() -> Vec<VoiceParameter> {
+        (0..VOICE_SIZE as u16)
+            .map(|number| VoiceParameter::try_from(number).expect("valid parameter number"))
+            .collect()
+    }
+
+    // Checks the operator number (1...6) and the envelope and name indexes.
+    fn is_valid(&self) -> bool {
+        match *self {
+            VoiceParameter::Operator(op, OperatorParameter::EgRate(i) | OperatorParameter::EgLevel(i)) =>
+                (1..=OPERATOR_COUNT).contains(&op) && i < 4,
+            VoiceParameter::Operator(op, _) => (1..=OPERATOR_COUNT).contains(&op),
+            VoiceParameter::PegRate(i) | VoiceParameter::PegLevel(i) => i < 4,
+            VoiceParameter::NameChar(i) => i < VOICE_NAME_LENGTH,
+            _ => true,
+        }
+    }
+
+    /// Gets the parameter number (0...154), or `None` if the operator
+    /// number or an index of the parameter is out of range.
+    pub fn number(&self) -> Option<u16> {
+        if !self.is_valid() {
+            return None;
+        }
+        let number = match *self {
+            VoiceParameter::Operator(op, param) => (OPERATOR_COUNT - op) * OPERATOR_SIZE + param.offset(),
+            VoiceParameter::PegRate(i) => 126 + i,
+            VoiceParameter::PegLevel(i) => 130 + i,
+            VoiceParameter::Algorithm => 134,
+            VoiceParameter::Feedback => 135,
+            VoiceParameter::OscSync => 136,
+            VoiceParameter::LfoSpeed => 137,
+            VoiceParameter::LfoDelay => 138,
+            VoiceParameter::LfoPmd => 139,
+            VoiceParameter::LfoAmd => 140,
+            VoiceParameter::LfoSync => 141,
+            VoiceParameter::LfoWaveform => 142,
+            VoiceParameter::PitchModSens => 143,
+            VoiceParameter::Transpose => 144,
+            VoiceParameter::NameChar(i) => 145 + i,
+        };
+        Some(number as u16)
+    }
+
+    /// Gets the largest value of this parameter in the SysEx data.
+    pub fn max_value(&self) -> u8 {
+        match *self {
+            VoiceParameter::Operator(_, param) => param.max_value(),
+            VoiceParameter::Algorithm => 31,
+            VoiceParameter::Feedback | VoiceParameter::PitchModSens => 7,
+            VoiceParameter::OscSync | VoiceParameter::LfoSync => 1,
+            VoiceParameter::LfoWaveform => 5,
+            VoiceParameter::Transpose => 48,
+            VoiceParameter::NameChar(_) => 127,
+            _ => 99,
+        }
+    }
+
+    /// Gets the path of this parameter in a voice, like `op3.eg.rates[1]`.
+    pub fn path(&self) -> String {
+        match *self {
+            VoiceParameter::Operator(op, param) => format!("op{}.{}", op, param.path()),
+            VoiceParameter::PegRate(i) => format!("peg.rates[{}]", i),
+            VoiceParameter::PegLevel(i) => format!("peg.levels[{}]", i),
+            VoiceParameter::Algorithm => "alg".to_string(),
+            VoiceParameter::Feedback => "feedback".to_string(),
+            VoiceParameter::OscSync => "osc_sync".to_string(),
+            VoiceParameter::LfoSpeed => "lfo.speed".to_string(),
+            VoiceParameter::LfoDelay => "lfo.delay".to_string(),
+            VoiceParameter::LfoPmd => "lfo.pmd".to_string(),
+            VoiceParameter::LfoAmd => "lfo.amd".to_string(),
+            VoiceParameter::LfoSync => "lfo.sync".to_string(),
+            VoiceParameter::LfoWaveform => "lfo.waveform".to_string(),
+            VoiceParameter::PitchModSens => "pitch_mod_sens".to_string(),
+            VoiceParameter::Transpose => "transpose".to_string(),
+            VoiceParameter::NameChar(i) => format!("name[{}]", i),
+        }
+    }
+}
+
+impl TryFrom<u16> for VoiceParameter {
+    type Error = &'static str;
+
+    fn try_from(number: u16) -> Result<Self, Self::Error> {
+        let number = number as usize;
+        Ok(match number {
+            0..126 => {
+                let op = OPERATOR_COUNT - number / OPERATOR_SIZE;  // OP6 is first
+                let param = OperatorParameter::from_offset(number % OPERATOR_SIZE)
+                    .expect("valid operator parameter offset");
+                VoiceParameter::Operator(op, param)
+            },
+            126..=129 => VoiceParameter::PegRate(number - 126),
+            130..=133 => VoiceParameter::PegLevel(number - 130),
+            134 => VoiceParameter::Algorithm,
+            135 => VoiceParameter::Feedback,
+            136 => VoiceParameter::OscSync,
+            137 => VoiceParameter::LfoSpeed,
+            138 => VoiceParameter::LfoDelay,
+            139 => VoiceParameter::LfoPmd,
+            140 => VoiceParameter::LfoAmd,
+            141 => VoiceParameter::LfoSync,
+            142 => VoiceParameter::LfoWaveform,
+            143 => VoiceParameter::PitchModSens,
+            144 => VoiceParameter::Transpose,
+            145..=154 => VoiceParameter::NameChar(number - 145),
+            _ => return Err("Bad voice parameter number, expected 0...154"),
+        })
+    }
+}
+
+impl fmt::Display for VoiceParameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path())
+    }
+}
+
+/// Function parameter, numbered 64...77 in the function group.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum FunctionParameter {
+    PolyMode,
+    BendRange,
+    BendStep,
+    PortamentoMode,
+    Glissando,
+    PortamentoTime,
+    ModWheelRange,
+    ModWheelAssign,
+    FootControlRange,
+    FootControlAssign,
+    BreathControlRange,
+    BreathControlAssign,
+    AftertouchRange,
+    AftertouchAssign,
+}
+
+impl FunctionParameter {
+    /// Gets all the function parameters in parameter number order.
+    pub fn all() -> Vec<FunctionParameter> {
+        (0..FUNCTION_SIZE as u16)
+            .map(|i| FunctionParameter::try_from(FIRST_FUNCTION_PARAMETER + i)
+                .expect("valid parameter number"))
+            .collect()
+    }
+
+    /// Gets the parameter number (64...77).
+    pub fn number(&self) -> u16 {
+        FIRST_FUNCTION_PARAMETER + *self as u16
+    }
+
+    /// Gets the path of this parameter in the function settings,
+    /// like `mod_wheel.range`.
+    pub fn path(&self) -> &'static str {
+        match *self {
+            FunctionParameter::PolyMode => "poly_mode",
+            FunctionParameter::BendRange => "bend_range",
+            FunctionParameter::BendStep => "bend_step",
+            FunctionParameter::PortamentoMode => "portamento_mode",
+            FunctionParameter::Glissando => "glissando",
+            FunctionParameter::PortamentoTime => "portamento_time",
+            FunctionParameter::ModWheelRange => "mod_wheel.range",
+            FunctionParameter::ModWheelAssign => "mod_wheel.assign",
+            FunctionParameter::FootControlRange => "foot_control.range",
+            FunctionParameter::FootControlAssign => "foot_control.assign",
+            FunctionParameter::BreathControlRange => "breath_control.range",
+            FunctionParameter::BreathControlAssign => "breath_control.assign",
+            FunctionParameter::AftertouchRange => "aftertouch.range",
+            FunctionParameter::AftertouchAssign => "aftertouch.assign",
+        }
+    }
+}
+
+impl TryFrom<u16> for FunctionParameter {
+    type Error = &'static str;
+
+    fn try_from(number: u16) -> Result<Self, Self::Error> {
+        Ok(match number {
+            64 => FunctionParameter::PolyMode,
+            65 => FunctionParameter::BendRange,
+            66 => FunctionParameter::BendStep,
+            67 => FunctionParameter::PortamentoMode,
+            68 => FunctionParameter::Glissando,
+            69 => FunctionParameter::PortamentoTime,
+            70 => FunctionParameter::ModWheelRange,
+            71 => FunctionParameter::ModWheelAssign,
+            72 => FunctionParameter::FootControlRange,
+            73 => FunctionParameter::FootControlAssign,
+            74 => FunctionParameter::BreathControlRange,
+            75 => FunctionParameter::BreathControlAssign,
+            76 => FunctionParameter::AftertouchRange,
+            77 => FunctionParameter::AftertouchAssign,
+            _ => return Err("Bad function parameter number, expected 64...77"),
+        })
+    }
+}
+
+impl fmt::Display for FunctionParameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path())
+    }
+}
+
+/// A parameter change message.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ParameterChange {
+    pub channel: MidiChannel,
+    pub group: u8,  // 0 = voice, 2 = function
+    pub number: u16,  // parameter number in the group
+    pub value: u8,  // value as in SysEx data
+}
+
+impl ParameterChange {
+    /// Makes a voice parameter change message, or returns `None`
+    /// if the parameter is out of range.
+    pub fn voice(channel: MidiChannel, parameter: VoiceParameter, value: u8) -> Option<Self> {
+        Some(Self {
+            channel,
+            group: VOICE_GROUP,
+            number: parameter.number()?,
+            value,
+        })
+    }
+
+    /// Makes a function parameter change message.
+    pub fn function(channel: MidiChannel, parameter: FunctionParameter, value: u8) -> Self {
+        Self {
+            channel,
+            group: FUNCTION_GROUP,
+            number: parameter.number(),
+            value,
+        }
+    }
+
+    /// Gets the function parameter that this message changes,
+    /// or `None` if it is not a function parameter change.
+    pub fn function_parameter(&self) -> Option<FunctionParameter> {
+        if self.group != FUNCTION_GROUP {
+            return None;
+        }
+        FunctionParameter::try_from(self.number).ok()
+    }
+
+    /// Gets the voice parameter that this message changes,
+    /// or `None` if it is not a voice parameter change.
+    pub fn voice_parameter(&self) -> Option<VoiceParameter> {
+        if self.group != VOICE_GROUP {
+            return None;
+        }
+        VoiceParameter::try_from(self.number).ok()
+    }
+}
+
+impl fmt::Display for ParameterChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(parameter) = self.voice_parameter() {
+            write!(f, "{} = {}", parameter, self.value)
+        } else if let Some(parameter) = self.function_parameter() {
+            write!(f, "{} = {}", parameter, self.value)
+        } else {
+            write!(f, "group {} parameter {} = {}", self.group, self.number, self.value)
+        }
+    }
+}
+
+impl SystemExclusiveData for ParameterChange {
+    /// Parses a complete parameter change message.
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        check_length(data, Self::data_size(), "parameter change")?;
+
+        if data[0] != INITIATOR || data[6] != TERMINATOR {
+            return Err(ParseError::InvalidMessage);
+        }
+
+        if data[1] != YAMAHA {
+            return Err(ParseError::InvalidManufacturer);
+        }
+
+        if (data[2] >> 4) & 0b00000111 != PARAMETER_SUB_STATUS {
+            return Err(invalid_field(2, "sub-status", "not a parameter change"));
+        }
+
+        Ok(Self {
+            channel: MidiChannel::new(((data[2] & 0b00001111) + 1) as i32),
+            group: (data[3] >> 2) & 0b00011111,
+            number: (((data[3] & 0b00000011) as u16) << 7) | (data[4] as u16),
+            value: data[5],
+        })
+    }
+
+    /// Gets the complete message bytes.
+    fn to_bytes(&self) -> Vec<u8> {
+        vec![
+            INITIATOR,
+            YAMAHA,
+            self.channel.encode() | (PARAMETER_SUB_STATUS << 4),
+            (self.group << 2) | ((self.number >> 7) as u8 & 0b00000011),
+            (self.number & 0x7f) as u8,
+            self.value,
+            TERMINATOR,
+        ]
+    }
+
+    fn data_size() -> usize { 7 }
+}
+
+impl Voice {
+    /// Makes a parameter change message with the current value
+    /// of `parameter` in this voice, or returns `None` if the parameter
+    /// is out of range.
+    pub fn parameter_change(&self, channel: MidiChannel, parameter: VoiceParameter) -> Option<ParameterChange> {
+        let data = self.to_bytes();
+        ParameterChange::voice(channel, parameter, data[parameter.number()? as usize])
+    }
+
+    /// Applies a received voice parameter change to this voice.
+    /// Returns an error if the message is not a voice parameter change,
+    /// or if the value is out of range for the parameter.
+    pub fn apply(&mut self, change: &ParameterChange) -> Result<(), ParseError> {
+        let parameter = change.voice_parameter()
+            .ok_or_else(|| invalid_field(4, "parameter", "not a voice parameter"))?;
+
+        let mut data = self.to_bytes();
+        let offset = parameter.number().expect("valid parameter") as usize;
+        data[offset] = change.value;
+        *self = Voice::parse_with(&data, &mut ParseContext::strict())?;
+        Ok(())
+    }
+}
+
+impl Function {
+    /// Makes a parameter change message with the current value
+    /// of `parameter` in these function settings.
+    pub fn parameter_change(&self, channel: MidiChannel, parameter: FunctionParameter) -> ParameterChange {
+        let data = self.to_bytes();
+        let offset = (parameter.number() - FIRST_FUNCTION_PARAMETER) as usize;
+        ParameterChange::function(channel, parameter, data[offset])
+    }
+
+    /// Applies a received function parameter change to these settings.
+    /// Returns an error if the message is not a function parameter change,
+    /// or if the value is out of range for the parameter.
+    pub fn apply(&mut self, change: &ParameterChange) -> Result<(), ParseError> {
+        let parameter = change.function_parameter()
+            .ok_or_else(|| invalid_field(4, "parameter", "not a function parameter"))?;
+
+        let mut data = self.to_bytes();
+        let offset = (parameter.number() - FIRST_FUNCTION_PARAMETER) as usize;
+        data[offset] = change.value;
+        *self = Function::parse_with(&data, &mut ParseContext::strict())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parameter_numbers() {
+        let all = VoiceParameter::all();
+        assert_eq!(all.len(), 155);
+        assert_eq!(all[0], VoiceParameter::Operator(6, OperatorParameter::EgRate(0)));
+        assert_eq!(VoiceParameter::Operator(1, OperatorParameter::Detune).number(), Some(125));
+        assert_eq!(VoiceParameter::Algorithm.number(), Some(134));
+        for (number, parameter) in all.iter().enumerate() {
+            assert_eq!(parameter.number(), Some(number as u16));
+        }
+        assert_eq!(VoiceParameter::Operator(0, OperatorParameter::Detune).number(), None);
+        assert_eq!(VoiceParameter::Operator(7, OperatorParameter::Detune).number(), None);
+        assert_eq!(VoiceParameter::Operator(1, OperatorParameter::EgRate(4)).number(), None);
+        assert_eq!(VoiceParameter::PegLevel(4).number(), None);
+        assert_eq!(VoiceParameter::NameChar(10).number(), None);
+        assert_eq!(VoiceParameter::Operator(2, OperatorParameter::Detune).max_value(), 14);
+        assert_eq!(VoiceParameter::Transpose.max_value(), 48);
+    }
+
+    #[test]
+    fn test_parameter_change_bytes() {
+        let change = ParameterChange::voice(
+            MidiChannel::new(2),
+            VoiceParameter::NameChar(9),  // parameter 154
+            0x41).unwrap();
+        let data = change.to_bytes();
+        assert_eq!(data, vec![0xF0, 0x43, 0x11, 0x01, 0x1A, 0x41, 0xF7]);
+        assert_eq!(ParameterChange::parse(&data).unwrap(), change);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut voice = Voice::new();
+        let parameter = VoiceParameter::Operator(3, OperatorParameter::OutputLevel);
+        let change = ParameterChange::voice(MidiChannel::new(1), parameter, 87).unwrap();
+        voice.apply(&change).expect("valid change");
+        assert_eq!(voice.operators[2].output_level.value(), 87);
+        assert_eq!(voice.parameter_change(MidiChannel::new(1), parameter), Some(change));
+
+        let bad = ParameterChange::voice(MidiChannel::new(1), VoiceParameter::Algorithm, 32).unwrap();
+        assert!(voice.apply(&bad).is_err());
+        assert_eq!(voice.alg.value(), 1);
+    }
+
+    #[test]
+    fn test_function_parameter_change() {
+        let mut function = Function::new();
+        let change = ParameterChange::function(
+            MidiChannel::new(1), FunctionParameter::BendRange, 12);
+        assert_eq!(change.to_bytes(), vec![0xF0, 0x43, 0x10, 0x08, 0x41, 0x0C, 0xF7]);
+
+        function.apply(&change).expect("valid change");
+        assert_eq!(function.bend_range.value(), 12);
+        assert_eq!(function.parameter_change(MidiChannel::new(1), FunctionParameter::BendRange), change);
+        assert_eq!(FunctionParameter::all().len(), 14);
+        assert!(Voice::new().apply(&change).is_err());
+    }
+}
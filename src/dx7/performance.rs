@@ -0,0 +1,415 @@
+use std::fmt;
+
+use rand::Rng;
+use syxpack::{
+    ParseError,
+    Ranged,
+    ranged_impl,
+    Encoding,
+    SystemExclusiveData,
+};
+
+use crate::dx7::{
+    Depth,
+    Level,
+    Transpose,
+    check_length,
+    name_bytes,
+};
+use crate::dx7::envelope::{
+    Envelope,
+    Rate,
+};
+use crate::dx7::operator::Key;
+use crate::dx7::policy::ParseContext;
+
+/// Size of the DX7II performance data (PCED).
+pub const PERFORMANCE_SIZE: usize = 51;
+
+/// Number of performances in a PMEM bank.
+pub const PERFORMANCE_COUNT: usize = 32;
+
+/// Size of the PMEM bank data.
+pub const PERFORMANCE_BANK_SIZE: usize = PERFORMANCE_COUNT * PERFORMANCE_SIZE;
+
+/// Length of the performance name.
+pub const PERFORMANCE_NAME_LENGTH: usize = 20;
+
+/// Voice number in DX7II memory (1...128). Voices 1...64 are internal,
+/// 65...128 are in the cartridge.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct VoiceNumber(i32);
+
+ranged_impl!(VoiceNumber, 1, 128, 1);
+
+impl Encoding for VoiceNumber {
+    fn decode(b: u8) -> i32 {
+        (b as i32) + 1  // adjust to 1...128
+    }
+
+    fn encode(&self) -> u8 {
+        (self.value() - 1) as u8  // adjust to 0...127 for SysEx
+    }
+}
+
+/// Micro tuning table (0...12). Tables 0...10 are presets,
+/// 11 and 12 are user tables.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TuningTable(i32);
+
+ranged_impl!(TuningTable, 0, 12, 0);
+
+impl Encoding for TuningTable { }  // identity mapping, no adjustment needed
+
+/// Micro tuning key, the tonic of the tuning (0...11 = C...B).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TuningKey(i32);
+
+ranged_impl!(TuningKey, 0, 11, 0);
+
+impl Encoding for TuningKey { }  // identity mapping, no adjustment needed
+
+/// Balance between voices A and B (-50...+50), represented in SysEx as 0...100.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Balance(i32);
+
+ranged_impl!(Balance, -50, 50, 0);
+
+impl Encoding for Balance {
+    fn decode(b: u8) -> i32 {
+        (b as i32) - 50  // adjust to -50...+50
+    }
+
+    fn encode(&self) -> u8 {
+        (self.value() + 50) as u8  // adjust to 0...100 for SysEx
+    }
+}
+
+/// Performance play mode.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum PlayMode {
+    Single,
+    Dual,
+    Split,
+}
+
+impl TryFrom<u8> for PlayMode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(PlayMode::Single),
+            1 => Ok(PlayMode::Dual),
+            2 => Ok(PlayMode::Split),
+            _ => Err("Bad play mode value, expected 0...2")
+        }
+    }
+}
+
+impl From<PlayMode> for u8 {
+    fn from(mode: PlayMode) -> u8 {
+        mode as u8
+    }
+}
+
+impl fmt::Display for PlayMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            PlayMode::Single => "single",
+            PlayMode::Dual => "dual",
+            PlayMode::Split => "split",
+        })
+    }
+}
+
+/// Voices that use micro tuning.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum TuningSwitch {
+    Off,
+    VoiceA,
+    VoiceB,
+    Both,
+}
+
+impl TryFrom<u8> for TuningSwitch {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(TuningSwitch::Off),
+            1 => Ok(TuningSwitch::VoiceA),
+            2 => Ok(TuningSwitch::VoiceB),
+            3 => Ok(TuningSwitch::Both),
+            _ => Err("Bad micro tuning switch value, expected 0...3")
+        }
+    }
+}
+
+impl From<TuningSwitch> for u8 {
+    fn from(switch: TuningSwitch) -> u8 {
+        switch as u8
+    }
+}
+
+impl fmt::Display for TuningSwitch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            TuningSwitch::Off => "off",
+            TuningSwitch::VoiceA => "A",
+            TuningSwitch::VoiceB => "B",
+            TuningSwitch::Both => "A+B",
+        })
+    }
+}
+
+/// DX7II performance (PCED): two voices in single, dual or split mode.
+/// The layout follows the performance data list (PCED, 51 bytes)
+/// in the MIDI data format section of the DX7IID/FD owner's manual.
+/// The foot switch, slider and pan mode settings are kept as they are.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Performance {
+    pub play_mode: PlayMode,  // 0
+    pub voice_a: VoiceNumber,  // 1: 1 ~ 128
+    pub voice_b: VoiceNumber,  // 2: 1 ~ 128
+    pub tuning_table: TuningTable,  // 3: 0 ~ 12
+    pub tuning_key: TuningKey,  // 4: 0 ~ 11
+    pub tuning_switch: TuningSwitch,  // 5
+    pub dual_detune: Depth,  // 6: 0 ~ 7
+    pub split_point: Key,  // 7: 0 ~ 99
+    pub eg_forced_damp: bool,  // 8
+    pub foot_switches: [u8; 4],  // 9 ~ 12: sustain and foot switch settings as is
+    pub key_shift_a: Transpose,  // 13: -24 ~ +24
+    pub key_shift_b: Transpose,  // 14: -24 ~ +24
+    pub balance: Balance,  // 15: -50 ~ +50
+    pub total_volume: Level,  // 16: 0 ~ 99
+    pub sliders: [u8; 3],  // 17 ~ 19: continuous slider assignments and switch as is
+    pub pan_mode: u8,  // 20: as is
+    pub pan_range: Level,  // 21: 0 ~ 99
+    pub pan_assign: u8,  // 22: as is
+    pub pan_eg: Envelope,  // 23 ~ 30
+    pub name: String,  // 31 ~ 50, ASCII
+}
+
+impl Performance {
+    /// Makes a new performance with the DX7II defaults.
+    pub fn new() -> Self {
+        Self {
+            play_mode: PlayMode::Single,
+            voice_a: VoiceNumber::new(1),
+            voice_b: VoiceNumber::new(1),
+            tuning_table: TuningTable::default(),
+            tuning_key: TuningKey::default(),
+            tuning_switch: TuningSwitch::Off,
+            dual_detune: Depth::new(0),
+            split_point: Key::default(),  // C3
+            eg_forced_damp: false,
+            foot_switches: [0; 4],
+            key_shift_a: Transpose::default(),
+            key_shift_b: Transpose::default(),
+            balance: Balance::default(),
+            total_volume: Level::new(99),
+            sliders: [0; 3],
+            pan_mode: 0,
+            pan_range: Level::new(99),
+            pan_assign: 0,
+            pan_eg: Envelope::new_rate_level(
+                [Rate::new(99); 4],
+                [Level::new(50); 4]),
+            name: "INIT PERFORMANCE".to_string(),
+        }
+    }
+
+    /// Makes a performance from SysEx bytes, handling out-of-range values
+    /// according to the context policy.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, PERFORMANCE_SIZE, "performance")?;
+
+        let mut foot_switches = [0; 4];
+        foot_switches.copy_from_slice(&data[9..13]);
+        let mut sliders = [0; 3];
+        sliders.copy_from_slice(&data[17..20]);
+
+        let name_offset = PERFORMANCE_SIZE - PERFORMANCE_NAME_LENGTH;
+        let name = context.name(data, name_offset, PERFORMANCE_NAME_LENGTH)?;
+
+        Ok(Self {
+            play_mode: context.choice(data, 0, "play_mode", PlayMode::try_from, PlayMode::Single)?,
+            voice_a: context.ranged::<VoiceNumber>(data, 1, "voice_a")?,
+            voice_b: context.ranged::<VoiceNumber>(data, 2, "voice_b")?,
+            tuning_table: context.ranged::<TuningTable>(data, 3, "tuning_table")?,
+            tuning_key: context.ranged::<TuningKey>(data, 4, "tuning_key")?,
+            tuning_switch: context.choice(data, 5, "tuning_switch", TuningSwitch::try_from, TuningSwitch::Off)?,
+            dual_detune: context.ranged::<Depth>(data, 6, "dual_detune")?,
+            split_point: context.ranged::<Key>(data, 7, "split_point")?,
+            eg_forced_damp: context.switch(data, 8, "eg_forced_damp")?,
+            foot_switches,
+            key_shift_a: context.ranged::<Transpose>(data, 13, "key_shift_a")?,
+            key_shift_b: context.ranged::<Transpose>(data, 14, "key_shift_b")?,
+            balance: context.ranged::<Balance>(data, 15, "balance")?,
+            total_volume: context.ranged::<Level>(data, 16, "total_volume")?,
+            sliders,
+            pan_mode: data[20],
+            pan_range: context.ranged::<Level>(data, 21, "pan_range")?,
+            pan_assign: data[22],
+            pan_eg: context.nested("pan_eg", 23, |c| Envelope::parse_with(&data[23..31], c))?,
+            name,
+        })
+    }
+}
+
+impl Default for Performance {
+    fn default() -> Self {
+        Performance::new()
+    }
+}
+
+impl SystemExclusiveData for Performance {
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![
+            self.play_mode.into(),
+            self.voice_a.encode(),
+            self.voice_b.encode(),
+            self.tuning_table.encode(),
+            self.tuning_key.encode(),
+            self.tuning_switch.into(),
+            self.dual_detune.encode(),
+            self.split_point.encode(),
+            if self.eg_forced_damp { 1 } else { 0 },
+        ];
+        data.extend(self.foot_switches);
+        data.push(self.key_shift_a.encode());
+        data.push(self.key_shift_b.encode());
+        data.push(self.balance.encode());
+        data.push(self.total_volume.encode());
+        data.extend(self.sliders);
+        data.push(self.pan_mode);
+        data.push(self.pan_range.encode());
+        data.push(self.pan_assign);
+        data.extend(self.pan_eg.to_bytes());
+        data.extend(name_bytes(&self.name, PERFORMANCE_NAME_LENGTH));
+
+        assert_eq!(data.len(), PERFORMANCE_SIZE);
+        data
+    }
+
+    fn data_size() -> usize { PERFORMANCE_SIZE }
+}
+
+impl fmt::Display for Performance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}
+Mode: {}, voice A = {}, voice B = {}
+Micro tuning: table = {}, key = {}, switch = {}
+Dual detune: {}, split point: {}
+Balance: {}, volume = {}, key shift A = {}, key shift B = {}",
+            self.name,
+            self.play_mode, self.voice_a, self.voice_b,
+            self.tuning_table, self.tuning_key, self.tuning_switch,
+            self.dual_detune, self.split_point.name(),
+            self.balance, self.total_volume, self.key_shift_a, self.key_shift_b)
+    }
+}
+
+/// PMEM bank of 32 DX7II performances.
+#[derive(Debug, Clone)]
+pub struct PerformanceBank {
+    pub performances: Vec<Performance>,
+}
+
+impl PerformanceBank {
+    /// Makes a performance bank from SysEx bytes, handling out-of-range
+    /// values according to the context policy.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, PERFORMANCE_BANK_SIZE, "performance bank")?;
+
+        let mut performances = Vec::<Performance>::new();
+        for (i, performance_data) in data.chunks(PERFORMANCE_SIZE).enumerate() {
+            let offset = i * PERFORMANCE_SIZE;
+            let performance = context.nested(&format!("performances[{}]", i), offset,
+                |c| Performance::parse_with(performance_data, c))?;
+            performances.push(performance);
+        }
+        Ok(PerformanceBank { performances })
+    }
+}
+
+impl Default for PerformanceBank {
+    fn default() -> Self {
+        PerformanceBank {
+            performances: vec![Default::default(); PERFORMANCE_COUNT],
+        }
+    }
+}
+
+impl SystemExclusiveData for PerformanceBank {
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for performance in &self.performances {
+            data.extend(performance.to_bytes());
+        }
+        data
+    }
+
+    fn data_size() -> usize { PERFORMANCE_BANK_SIZE }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_performance_round_trip() {
+        let mut performance = Performance {
+            play_mode: PlayMode::Split,
+            voice_a: VoiceNumber::new(128),
+            voice_b: VoiceNumber::new(3),
+            tuning_switch: TuningSwitch::Both,
+            balance: Balance::new(-10),
+            key_shift_a: Transpose::new(12),
+            key_shift_b: Transpose::new(-12),
+            total_volume: Level::new(80),
+            name: "Split Brass/Strings".to_string(),
+            ..Performance::new()
+        };
+        performance.sliders[1] = 42;  // unmodeled bytes survive
+        let data = performance.to_bytes();
+        assert_eq!(data.len(), PERFORMANCE_SIZE);
+        assert_eq!(data[1], 127);
+        assert_eq!(data[7], 39);  // split point C3
+        assert_eq!(&data[13..17], &[36, 12, 40, 80]);
+        assert_eq!(data[18], 42);
+
+        let parsed = Performance::parse(&data).unwrap();
+        assert_eq!(parsed.name, "Split Brass/Strings ");
+        assert_eq!(parsed.to_bytes(), data);
+    }
+
+    #[test]
+    fn test_non_ascii_name() {
+        let performance = Performance { name: "Ströme über Köln".to_string(), ..Performance::new() };
+        let data = performance.to_bytes();
+        assert!(data.iter().all(|b| b.is_ascii()));
+        assert_eq!(Performance::parse(&data).unwrap().name, "Str me  ber K ln    ");
+    }
+
+    #[test]
+    fn test_bank_error_path() {
+        let mut data = PerformanceBank::default().to_bytes();
+        data[2 * PERFORMANCE_SIZE] = 7;  // bad play mode
+        let result = PerformanceBank::parse_with(&data, &mut ParseContext::strict());
+        assert_eq!(
+            result.unwrap_err(),
+            ParseError::InvalidData(102,
+                "performances[2].play_mode: Bad play mode value, expected 0...2".to_string()));
+    }
+}
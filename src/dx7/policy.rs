@@ -0,0 +1,234 @@
+use std::fmt;
+
+use log::warn;
+use syxpack::{
+    ParseError,
+    Ranged,
+    Encoding,
+};
+
+use crate::dx7::{
+    invalid_field,
+    nested_error,
+};
+
+/// Policy for handling out-of-range values when parsing SysEx data.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ParsePolicy {
+    /// Reject the data if any value is out of range.
+    Strict,
+
+    /// Clamp or default any out-of-range values, and report them as repairs.
+    Lenient,
+}
+
+impl fmt::Display for ParsePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            ParsePolicy::Strict => "strict",
+            ParsePolicy::Lenient => "lenient",
+        })
+    }
+}
+
+/// A parameter value that was repaired while parsing leniently.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Repair {
+    pub voice: Option<usize>,  // index of the voice in a cartridge
+    pub operator: Option<usize>,  // operator number 1...6
+    pub parameter: String,  // path of the parameter in the voice, like "op3.eg.rates[1]"
+    pub raw: u8,  // the byte found in the data
+    pub value: i32,  // the value used instead
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(voice) = self.voice {
+            write!(f, "voice {}: ", voice + 1)?;
+        }
+        write!(f, "{} raw value {} replaced with {}", self.parameter, self.raw, self.value)
+    }
+}
+
+/// Parsing state: the policy to follow, and the repairs made so far.
+#[derive(Debug, Clone)]
+pub struct ParseContext {
+    pub policy: ParsePolicy,
+    pub repairs: Vec<Repair>,
+    pub voice: Option<usize>,
+    pub operator: Option<usize>,
+    path: Vec<String>,
+}
+
+impl ParseContext {
+    /// Makes a new parse context with the given policy.
+    pub fn new(policy: ParsePolicy) -> Self {
+        Self {
+            policy,
+            repairs: Vec::new(),
+            voice: None,
+            operator: None,
+            path: Vec::new(),
+        }
+    }
+
+    /// Makes a new context for strict parsing.
+    pub fn strict() -> Self {
+        Self::new(ParsePolicy::Strict)
+    }
+
+    /// Makes a new context for lenient parsing.
+    pub fn lenient() -> Self {
+        Self::new(ParsePolicy::Lenient)
+    }
+
+    /// Gets the full path of the parameter `name` in the current nesting.
+    pub fn path(&self, name: &str) -> String {
+        let mut parts = self.path.clone();
+        parts.push(name.to_string());
+        parts.join(".")
+    }
+
+    /// Parses a nested structure whose data starts at offset `base`
+    /// in the enclosing data. Parameter paths and error messages
+    /// are prefixed with `prefix`, and error offsets are adjusted.
+    pub fn nested<T, F>(&mut self, prefix: &str, base: usize, parse: F) -> Result<T, ParseError>
+    where F: FnOnce(&mut Self) -> Result<T, ParseError>
+    {
+        self.path.push(prefix.to_string());
+        let result = parse(self);
+        self.path.pop();
+        result.map_err(|e| nested_error(e, base, prefix))
+    }
+
+    /// Handles a value that is out of range: fails with a strict policy,
+    /// or records the repair with a lenient policy.
+    pub fn repair(&mut self, offset: usize, name: &str, raw: u8, value: i32) -> Result<(), ParseError> {
+        match self.policy {
+            ParsePolicy::Strict => {
+                Err(invalid_field(offset, name, &format!("value out of range (raw: {})", raw)))
+            },
+            ParsePolicy::Lenient => {
+                let parameter = self.path(name);
+                warn!("{} out of range (raw: {}), using {}", parameter, raw, value);
+                self.repairs.push(Repair {
+                    voice: self.voice,
+                    operator: self.operator,
+                    parameter,
+                    raw,
+                    value,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Parses a ranged value from the byte at `offset`,
+    /// using the default value of the type if it is out of range.
+    pub fn ranged<R>(&mut self, data: &[u8], offset: usize, name: &str) -> Result<R, ParseError>
+    where R: Ranged + Encoding + Default
+    {
+        let value = R::decode(data[offset]);
+        if R::contains(value) {
+            Ok(R::new(value))
+        } else {
+            let result = R::default();
+            self.repair(offset, name, data[offset], result.value())?;
+            Ok(result)
+        }
+    }
+
+    /// Parses a ranged value from the byte at `offset`,
+    /// clamping it to the range of the type if necessary.
+    pub fn clamped<R>(&mut self, data: &[u8], offset: usize, name: &str) -> Result<R, ParseError>
+    where R: Ranged + Encoding
+    {
+        let value = R::decode(data[offset]);
+        if R::contains(value) {
+            Ok(R::new(value))
+        } else {
+            let result = num::clamp(value, R::FIRST, R::LAST);
+            self.repair(offset, name, data[offset], result)?;
+            Ok(R::new(result))
+        }
+    }
+
+    /// Parses an enumerated value from the byte at `offset` with `convert`,
+    /// using `fallback` if the conversion fails.
+    pub fn choice<T, F>(&mut self, data: &[u8], offset: usize, name: &str, convert: F, fallback: T) -> Result<T, ParseError>
+    where
+        T: Copy + Into<u8>,
+        F: FnOnce(u8) -> Result<T, &'static str>
+    {
+        match convert(data[offset]) {
+            Ok(value) => Ok(value),
+            Err(e) if self.policy == ParsePolicy::Strict => {
+                Err(invalid_field(offset, name, e))
+            },
+            Err(_) => {
+                self.repair(offset, name, data[offset], fallback.into() as i32)?;
+                Ok(fallback)
+            }
+        }
+    }
+
+    /// Parses an ASCII name of `length` bytes at `offset`. Bytes with
+    /// the high bit set can't be valid SysEx data, so they are replaced
+    /// with spaces.
+    pub fn name(&mut self, data: &[u8], offset: usize, length: usize) -> Result<String, ParseError> {
+        let mut name_data = data[offset..offset + length].to_vec();
+        for (i, b) in name_data.iter_mut().enumerate() {
+            if !b.is_ascii() {
+                let parameter = format!("name[{}]", i);
+                self.repair(offset + i, &parameter, *b, b' ' as i32)
+                    .map_err(|_| invalid_field(offset + i, "name", "invalid character"))?;
+                *b = b' ';
+            }
+        }
+        Ok(String::from_utf8(name_data).expect("ASCII name"))
+    }
+
+    /// Parses a switch (0 = off, 1 = on) from the byte at `offset`.
+    pub fn switch(&mut self, data: &[u8], offset: usize, name: &str) -> Result<bool, ParseError> {
+        match data[offset] {
+            0 => Ok(false),
+            1 => Ok(true),
+            raw => {
+                self.repair(offset, name, raw, 0)?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl Default for ParseContext {
+    fn default() -> Self {
+        ParseContext::lenient()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dx7::Level;
+
+    #[test]
+    fn test_strict_rejects() {
+        let mut context = ParseContext::strict();
+        let result = context.ranged::<Level>(&[0, 120], 1, "level");
+        assert!(result.is_err());
+        assert!(context.repairs.is_empty());
+    }
+
+    #[test]
+    fn test_lenient_repairs() {
+        let mut context = ParseContext::lenient();
+        let level = context.nested("eg", 4, |c| c.clamped::<Level>(&[120], 0, "levels[0]"))
+            .expect("lenient parse");
+        assert_eq!(level.value(), 99);
+        assert_eq!(context.repairs.len(), 1);
+        assert_eq!(context.repairs[0].parameter, "eg.levels[0]");
+        assert_eq!(context.repairs[0].raw, 120);
+        assert_eq!(context.repairs[0].value, 99);
+    }
+}
@@ -0,0 +1,447 @@
+use std::f64::consts::PI;
+
+use syxpack::Ranged;
+
+use crate::dx7::{
+    Depth,
+    Note,
+    Sensitivity,
+    Velocity,
+};
+use crate::dx7::algorithm::Topology;
+use crate::dx7::envelope::{
+    self,
+    Envelope,
+};
+use crate::dx7::lfo::{
+    Lfo,
+    LfoOscillator,
+};
+use crate::dx7::operator::OperatorMode;
+use crate::dx7::tuning::Tuning;
+use crate::dx7::voice::{
+    Voice,
+    OPERATOR_COUNT,
+};
+
+// The synthesis model, including the envelopes, the LFO and keyboard
+// level scaling, follows the DX7 emulation in Music Synthesizer for
+// Android (MSFA), also used by Dexed. Envelopes and the LFO are
+// updated once per block of samples, like in the original.
+
+/// Default sample rate for rendering.
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+/// Number of samples between envelope and LFO updates.
+pub const BLOCK_SIZE: usize = 64;
+
+/// Longest release tail rendered after the key is released, in seconds.
+pub const MAX_RELEASE_SECONDS: f64 = 5.0;
+
+/// Offline DX7 voice renderer.
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    pub sample_rate: u32,
+    pub tuning: Tuning,
+}
+
+impl Renderer {
+    /// Makes a new renderer in equal temperament.
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            tuning: Tuning::equal_temperament(),
+        }
+    }
+
+    /// Makes a new renderer with a micro tuning.
+    pub fn with_tuning(sample_rate: u32, tuning: Tuning) -> Self {
+        Self { sample_rate, tuning }
+    }
+
+    /// Renders `note` played with `voice`. The key is held for `duration`
+    /// seconds and then released; the release is rendered until the carriers
+    /// fall silent, up to `MAX_RELEASE_SECONDS`. Returns mono samples
+    /// in the range -1.0...1.0.
+    pub fn render(&self, voice: &Voice, note: Note, velocity: Velocity, duration: f64) -> Vec<f32> {
+        let sample_rate = self.sample_rate as f64;
+        let held = (duration.max(0.0) * sample_rate) as usize;
+        let limit = held + (MAX_RELEASE_SECONDS * sample_rate) as usize;
+
+        let mut state = VoiceState::new(voice, note, velocity, self);
+        let mut samples = Vec::<f32>::with_capacity(held);
+        while samples.len() < limit {
+            if samples.len() == held {
+                state.key_up();
+            }
+            if samples.len() >= held && state.is_finished() {
+                break;
+            }
+            let count = if samples.len() < held {
+                BLOCK_SIZE.min(held - samples.len())
+            } else {
+                BLOCK_SIZE
+            };
+            state.render_block(count, &mut samples);
+        }
+        samples
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Renderer::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+// Operator envelope generator, with the level in internal units.
+#[derive(Debug, Clone)]
+struct OperatorEnvelope {
+    rates: [i32; 4],
+    levels: [i32; 4],
+    output_level: i32,
+    rate_scaling: i32,
+    level: f64,
+    target: f64,
+    speed: f64,
+    stage: usize,
+    down: bool,
+}
+
+impl OperatorEnvelope {
+    fn new(envelope: &Envelope, output_level: i32, rate_scaling: i32) -> Self {
+        let mut result = Self {
+            rates: envelope.rates.map(|r| r.value()),
+            levels: envelope.levels.map(|l| l.value()),
+            output_level,
+            rate_scaling,
+            level: 0.0,
+            target: 0.0,
+            speed: 0.0,
+            stage: 0,
+            down: true,
+        };
+        result.advance(0);
+        result
+    }
+
+    fn advance(&mut self, stage: usize) {
+        self.stage = stage;
+        if stage < 4 {
+            self.target = envelope::stage_level(self.levels[stage], self.output_level);
+            self.speed = envelope::level_speed(self.rates[stage], self.rate_scaling);
+        }
+    }
+
+    fn key_up(&mut self) {
+        self.down = false;
+        self.advance(3);
+    }
+
+    // Advances the envelope by `seconds`.
+    fn step(&mut self, seconds: f64) {
+        if self.stage < 3 || (self.stage < 4 && !self.down) {
+            self.level = envelope::approach(self.level, self.target, self.speed, seconds);
+            if self.level == self.target {
+                self.advance(self.stage + 1);
+            }
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.stage >= 4 || (!self.down && self.level <= envelope::SILENT_LEVEL)
+    }
+}
+
+static PITCH_RATE_TABLE: [i32; 100] = [
+    1, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13, 14, 14, 15, 16, 16, 17, 18, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28,
+    30, 31, 33, 34, 36, 37, 38, 39, 41, 42, 44, 46, 47, 49, 51, 53, 54, 56, 58,
+    60, 62, 64, 66, 68, 70, 72, 74, 76, 79, 82, 85, 88, 91, 94, 98, 102, 106,
+    110, 115, 120, 125, 130, 135, 141, 147, 153, 159, 165, 171, 178, 185, 193,
+    202, 211, 232, 243, 254, 255
+];
+
+static PITCH_LEVEL_TABLE: [i32; 100] = [
+    -128, -116, -104, -95, -85, -76, -68, -61, -56, -52, -49, -46, -43, -41,
+    -39, -37, -35, -33, -32, -31, -30, -29, -28, -27, -26, -25, -24, -23, -22,
+    -21, -20, -19, -18, -17, -16, -15, -14, -13, -12, -11, -10, -9, -8, -7, -6,
+    -5, -4, -3, -2, -1, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+    16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34,
+    35, 38, 40, 43, 46, 49, 53, 58, 65, 73, 82, 92, 103, 115, 127
+];
+
+// Pitch envelope generator. The level is in octaves.
+#[derive(Debug, Clone)]
+struct PitchEnvelope {
+    rates: [i32; 4],
+    levels: [i32; 4],
+    unit: f64,  // octaves per block at rate table value 1
+    level: f64,
+    target: f64,
+    increment: f64,
+    rising: bool,
+    stage: usize,
+    down: bool,
+}
+
+impl PitchEnvelope {
+    fn new(envelope: &Envelope, sample_rate: f64) -> Self {
+        let levels = envelope.levels.map(|l| l.value());
+        let mut result = Self {
+            rates: envelope.rates.map(|r| r.value()),
+            levels,
+            unit: BLOCK_SIZE as f64 / (21.3 * sample_rate),
+            level: PITCH_LEVEL_TABLE[levels[3] as usize] as f64 / 32.0,
+            target: 0.0,
+            increment: 0.0,
+            rising: false,
+            stage: 0,
+            down: true,
+        };
+        result.advance(0);
+        result
+    }
+
+    fn advance(&mut self, stage: usize) {
+        self.stage = stage;
+        if stage < 4 {
+            self.target = PITCH_LEVEL_TABLE[self.levels[stage] as usize] as f64 / 32.0;
+            self.rising = self.target > self.level;
+            self.increment = PITCH_RATE_TABLE[self.rates[stage] as usize] as f64 * self.unit;
+        }
+    }
+
+    fn key_up(&mut self) {
+        self.down = false;
+        self.advance(3);
+    }
+
+    fn step(&mut self, fraction: f64) {
+        if self.stage < 3 || (self.stage < 4 && !self.down) {
+            if self.rising {
+                self.level += self.increment * fraction;
+                if self.level >= self.target {
+                    self.level = self.target;
+                    self.advance(self.stage + 1);
+                }
+            } else {
+                self.level -= self.increment * fraction;
+                if self.level <= self.target {
+                    self.level = self.target;
+                    self.advance(self.stage + 1);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct OperatorState {
+    envelope: OperatorEnvelope,
+    log_frequency: f64,
+    fixed: bool,
+    amp_mod_sens: Sensitivity,
+    phase: f64,
+    gain: f64,
+    output: f64,
+}
+
+// Playing state of a voice.
+#[derive(Debug, Clone)]
+// The modulation routing of an algorithm, worked out once per note
+// so that the sample loop does not allocate. Operators are indexes,
+// with OP1 at index 0.
+struct Routing {
+    order: [usize; OPERATOR_COUNT],  // modulators before the operators they modulate
+    modulators: [[bool; OPERATOR_COUNT]; OPERATOR_COUNT],  // [operator][modulator]
+    carriers: [bool; OPERATOR_COUNT],
+    carrier_count: usize,
+    feedback: (usize, usize),  // from, to
+}
+
+impl Routing {
+    fn new(topology: &Topology) -> Self {
+        let mut order = [0; OPERATOR_COUNT];
+        for (slot, op) in order.iter_mut().zip(topology.evaluation_order()) {
+            *slot = op - 1;
+        }
+        let mut modulators = [[false; OPERATOR_COUNT]; OPERATOR_COUNT];
+        let mut carriers = [false; OPERATOR_COUNT];
+        for op in 1..=OPERATOR_COUNT {
+            for m in topology.modulators_of(op) {
+                modulators[op - 1][m - 1] = true;
+            }
+            carriers[op - 1] = topology.is_carrier(op);
+        }
+        let (from, to) = topology.feedback;
+        Self {
+            order,
+            modulators,
+            carriers,
+            carrier_count: topology.carriers.len(),
+            feedback: (from - 1, to - 1),
+        }
+    }
+}
+
+struct VoiceState {
+    operators: Vec<OperatorState>,  // OP1 first
+    routing: Routing,
+    feedback: f64,  // feedback scale in radians
+    feedback_history: [f64; 2],
+    pitch_envelope: PitchEnvelope,
+    lfo: Lfo,
+    oscillator: LfoOscillator,
+    pitch_mod_sens: Depth,
+    sample_rate: f64,
+}
+
+impl VoiceState {
+    fn new(voice: &Voice, note: Note, velocity: Velocity, renderer: &Renderer) -> Self {
+        let sample_rate = renderer.sample_rate as f64;
+        let note_frequency = voice.note_frequency(note, &renderer.tuning);
+
+        let operators = voice.operators.iter().map(|op| {
+            let output_level = op.scaled_output_level(note, velocity);
+            let rate_scaling = envelope::keyboard_rate_scaling(note, op.kbd_rate_scaling);
+            OperatorState {
+                envelope: OperatorEnvelope::new(&op.eg, output_level, rate_scaling),
+                log_frequency: op.frequency_at(note_frequency).hertz(note_frequency).log2(),
+                fixed: matches!(op.mode, OperatorMode::Fixed),
+                amp_mod_sens: op.amp_mod_sens,
+                phase: 0.0,
+                gain: 0.0,
+                output: 0.0,
+            }
+        }).collect();
+
+        let feedback = voice.feedback.value();
+        Self {
+            operators,
+            routing: Routing::new(&voice.alg.topology()),
+            feedback: if feedback == 0 { 0.0 } else { 4.0 * PI * 2.0_f64.powi(feedback - 8) },
+            feedback_history: [0.0; 2],
+            pitch_envelope: PitchEnvelope::new(&voice.peg, sample_rate),
+            lfo: voice.lfo,
+            oscillator: LfoOscillator::new(&voice.lfo),
+            pitch_mod_sens: voice.pitch_mod_sens,
+            sample_rate,
+        }
+    }
+
+    fn key_up(&mut self) {
+        for op in self.operators.iter_mut() {
+            op.envelope.key_up();
+        }
+        self.pitch_envelope.key_up();
+    }
+
+    fn is_finished(&self) -> bool {
+        self.operators.iter().zip(self.routing.carriers)
+            .all(|(op, carrier)| !carrier || op.envelope.is_finished())
+    }
+
+    fn render_block(&mut self, count: usize, samples: &mut Vec<f32>) {
+        let fraction = count as f64 / BLOCK_SIZE as f64;
+        let seconds = count as f64 / self.sample_rate;
+
+        // Modulation values are updated once per block.
+        let lfo_value = self.oscillator.advance(seconds);
+        let lfo_delay = self.oscillator.advance_delay(seconds);
+        let pitch_mod = self.lfo.pitch_modulation(self.pitch_mod_sens, lfo_value, lfo_delay);
+        let pitch = self.pitch_envelope.level + pitch_mod;
+        self.pitch_envelope.step(fraction);
+
+        let mut increments = [0.0; OPERATOR_COUNT];
+        let mut gains = [(0.0, 0.0); OPERATOR_COUNT];
+        for (i, op) in self.operators.iter_mut().enumerate() {
+            let log_frequency = if op.fixed { op.log_frequency } else { op.log_frequency + pitch };
+            increments[i] = 2.0_f64.powf(log_frequency) / self.sample_rate;
+
+            let attenuation = self.lfo.amplitude_modulation(op.amp_mod_sens, lfo_value, lfo_delay);
+            let level = op.envelope.level * (1.0 - attenuation);
+            op.envelope.step(seconds);
+            let gain = envelope::amplitude(level);
+            gains[i] = (op.gain, gain);
+            op.gain = gain;
+        }
+
+        let (feedback_from, feedback_to) = self.routing.feedback;
+        let carrier_scale = 1.0 / self.routing.carrier_count as f64;
+        for s in 0..count {
+            let t = (s + 1) as f64 / count as f64;
+            let mut mix = 0.0;
+            for i in self.routing.order {
+                let mut modulation: f64 = self.routing.modulators[i].iter().zip(&self.operators)
+                    .filter(|(is_modulator, _)| **is_modulator)
+                    .map(|(_, m)| m.output)
+                    .sum::<f64>() * 4.0 * PI;
+                if i == feedback_to {
+                    modulation += (self.feedback_history[0] + self.feedback_history[1]) / 2.0 * self.feedback;
+                }
+
+                let (start, end) = gains[i];
+                let gain = start + (end - start) * t;  // smooth the gain over the block
+                let op = &mut self.operators[i];
+                op.output = gain * (2.0 * PI * op.phase + modulation).sin();
+                op.phase = (op.phase + increments[i]).fract();
+
+                if i == feedback_from {
+                    self.feedback_history = [self.feedback_history[1], op.output];
+                }
+                if self.routing.carriers[i] {
+                    mix += op.output;
+                }
+            }
+            samples.push((mix * carrier_scale).clamp(-1.0, 1.0) as f32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dx7::{
+        Level,
+        rom1a,
+    };
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |a, s| a.max(s.abs()))
+    }
+
+    #[test]
+    fn test_render_init_voice() {
+        let mut voice = Voice::new();
+        voice.operators[0].output_level = Level::new(99);
+        let renderer = Renderer::default();
+        let samples = renderer.render(&voice, Note::new(69), Velocity::new(100), 0.5);
+        assert!(samples.len() >= 22050);
+        assert!(peak(&samples) > 0.1);
+
+        // A single carrier is a sine wave: count zero crossings for the pitch.
+        let held = &samples[4410..22050];
+        let crossings = held.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        let frequency = crossings as f64 / (held.len() as f64 / 44100.0);
+        assert!((frequency - 440.0).abs() < 5.0, "frequency = {}", frequency);
+    }
+
+    #[test]
+    fn test_render_silent_voice() {
+        let voice = Voice::new();  // all operators are at output level zero
+        let samples = Renderer::default().render(&voice, Note::new(60), Velocity::new(100), 0.1);
+        assert!(peak(&samples) < 0.001);
+    }
+
+    #[test]
+    fn test_render_rom_voices() {
+        let cartridge = rom1a();
+        let renderer = Renderer::new(22050);
+        for voice in cartridge.voices.iter().take(8) {
+            let samples = renderer.render(voice, Note::new(60), Velocity::new(100), 0.2);
+            assert!(samples.iter().all(|s| s.is_finite()));
+            assert!(peak(&samples) > 0.01, "{} is silent", voice.name);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+
+use serde::{
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+    de,
+};
+use syxpack::Ranged;
+
+use crate::dx7::{
+    Algorithm,
+    Coarse,
+    Depth,
+    Detune,
+    Level,
+    Note,
+    Sensitivity,
+    Transpose,
+    Velocity,
+};
+use crate::dx7::envelope::Rate;
+use crate::dx7::function::{
+    BendRange,
+    BendStep,
+};
+use crate::dx7::operator::{
+    Key,
+    ScalingCurve,
+};
+use crate::dx7::performance::{
+    Balance,
+    TuningKey,
+    TuningTable,
+    VoiceNumber,
+};
+use crate::dx7::supplement::{
+    PitchBias,
+    PortamentoStep,
+};
+use crate::dx7::tuning::Fine;
+use crate::dx7::cartridge::{
+    Cartridge,
+    VOICE_COUNT,
+};
+use crate::dx7::voice::{
+    Voice,
+    VoiceName,
+    VOICE_NAME_LENGTH,
+};
+
+// Serializes ranged values as plain numbers, and checks the range
+// when deserializing.
+macro_rules! ranged_serde {
+    ($($name:ident),+) => {
+        $(
+            impl Serialize for $name {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.serialize_i32(self.value())
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $name {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let value = i32::deserialize(deserializer)?;
+                    if $name::contains(value) {
+                        Ok($name::new(value))
+                    } else {
+                        Err(de::Error::custom(format!("Bad {} value {}, expected {}...{}",
+                            stringify!($name), value, $name::FIRST, $name::LAST)))
+                    }
+                }
+            }
+        )+
+    };
+}
+
+ranged_serde!(
+    Algorithm, Coarse, Depth, Detune, Level, Note, Sensitivity, Transpose, Velocity,
+    Rate, Key, Fine, BendRange, BendStep, PortamentoStep, PitchBias,
+    VoiceNumber, TuningTable, TuningKey, Balance
+);
+
+// Scaling curves are written like "lin-" or "exp+".
+impl Serialize for ScalingCurve {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for ScalingCurve {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(ScalingCurveVisitor)
+    }
+}
+
+struct ScalingCurveVisitor;
+
+impl de::Visitor<'_> for ScalingCurveVisitor {
+    type Value = ScalingCurve;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "one of \"lin-\", \"exp-\", \"exp+\" or \"lin+\"")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<ScalingCurve, E> {
+        value.parse().map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
+
+// Voice names must fit in the voice data.
+impl<'de> Deserialize<'de> for VoiceName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        if VoiceName::is_valid(&name) {
+            Ok(VoiceName::from_string(name))
+        } else {
+            Err(de::Error::custom(format!("Bad voice name \"{}\", expected at most {} ASCII characters",
+                name, VOICE_NAME_LENGTH)))
+        }
+    }
+}
+
+// The fields of a cartridge, before checking the number of voices.
+#[derive(Deserialize)]
+struct CartridgeFields {
+    voices: Vec<Voice>,
+}
+
+impl<'de> Deserialize<'de> for Cartridge {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = CartridgeFields::deserialize(deserializer)?;
+        if fields.voices.len() == VOICE_COUNT {
+            Ok(Cartridge { voices: fields.voices })
+        } else {
+            Err(de::Error::custom(format!("Bad cartridge with {} voices, expected {}",
+                fields.voices.len(), VOICE_COUNT)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dx7::lfo::LfoWaveform;
+    use crate::dx7::rom1a;
+    use syxpack::SystemExclusiveData;
+
+    #[test]
+    fn test_voice_round_trip() {
+        let cartridge = rom1a();
+        let json = serde_json::to_string(&cartridge).unwrap();
+        let result: Cartridge = serde_json::from_str(&json).unwrap();
+        assert_eq!(result.to_bytes(), cartridge.to_bytes());
+    }
+
+    #[test]
+    fn test_field_names() {
+        let voice = Voice::new();
+        let value = serde_json::to_value(voice).unwrap();
+        assert_eq!(value["name"], "INIT VOICE");
+        assert_eq!(value["algorithm"], 1);
+        assert_eq!(value["lfo"]["waveform"], "triangle");
+        assert_eq!(value["operators"][0]["mode"], "ratio");
+        assert_eq!(value["operators"][0]["level_scaling"]["left"]["curve"], "lin-");
+
+        assert_eq!(serde_json::to_value(LfoWaveform::SampleAndHold).unwrap(), "sample-and-hold");
+        let curve: ScalingCurve = serde_json::from_str("\"exp-\"").unwrap();
+        assert_eq!(curve, ScalingCurve::exp_neg());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert_eq!(serde_json::from_str::<Level>("99").unwrap(), Level::new(99));
+        assert!(serde_json::from_str::<Level>("100").is_err());
+        assert!(serde_json::from_str::<Detune>("-8").is_err());
+        assert!(serde_json::from_str::<ScalingCurve>("\"log+\"").is_err());
+    }
+
+    #[test]
+    fn test_invalid_name_and_voice_count() {
+        let mut value = serde_json::to_value(Voice::new()).unwrap();
+        value["name"] = "TOO LONG NAME".into();
+        assert!(serde_json::from_value::<Voice>(value.clone()).is_err());
+        value["name"] = "ÄÖ".into();
+        assert!(serde_json::from_value::<Voice>(value.clone()).is_err());
+        value["name"] = "SHORT".into();
+        let voice: Voice = serde_json::from_value(value).unwrap();
+        assert_eq!(voice.name.value(), "SHORT");
+
+        let mut value = serde_json::to_value(Cartridge::default()).unwrap();
+        value["voices"].as_array_mut().unwrap().truncate(3);
+        let error = serde_json::from_value::<Cartridge>(value).unwrap_err();
+        assert_eq!(error.to_string(), "Bad cartridge with 3 voices, expected 32");
+    }
+}
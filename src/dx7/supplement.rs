@@ -0,0 +1,708 @@
+use std::fmt;
+
+use bit::BitIndex;
+use rand::Rng;
+use syxpack::{
+    ParseError,
+    Ranged,
+    ranged_impl,
+    Encoding,
+    MidiChannel,
+    SystemExclusiveData,
+};
+
+use crate::dx7::{
+    Depth,
+    Level,
+    check_length,
+};
+use crate::dx7::cartridge::Cartridge;
+use crate::dx7::function::{
+    BendRange,
+    BendStep,
+    PortamentoMode,
+};
+use crate::dx7::policy::ParseContext;
+use crate::dx7::sysex::Message;
+use crate::dx7::voice::{
+    Voice,
+    OPERATOR_COUNT,
+};
+
+/// Size of the additional voice data (ACED) of the DX7II and TX802.
+pub const SUPPLEMENT_SIZE: usize = 49;
+
+/// Size of the packed additional voice data in an AMEM bank.
+pub const SUPPLEMENT_PACKED_SIZE: usize = 35;
+
+/// Number of supplements in an AMEM bank.
+pub const SUPPLEMENT_COUNT: usize = 32;
+
+/// Size of the AMEM bank data.
+pub const SUPPLEMENT_BANK_SIZE: usize = SUPPLEMENT_COUNT * SUPPLEMENT_PACKED_SIZE;
+
+/// Portamento step (0...12). Zero means continuous portamento.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PortamentoStep(i32);
+
+ranged_impl!(PortamentoStep, 0, 12, 0);
+
+impl Encoding for PortamentoStep { }  // identity mapping, no adjustment needed
+
+/// Pitch bias (-50...+50), represented in SysEx as 0...100.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PitchBias(i32);
+
+ranged_impl!(PitchBias, -50, 50, 0);
+
+impl Encoding for PitchBias {
+    fn decode(b: u8) -> i32 {
+        (b as i32) - 50  // adjust to -50...+50
+    }
+
+    fn encode(&self) -> u8 {
+        (self.value() + 50) as u8  // adjust to 0...100 for SysEx
+    }
+}
+
+/// Operator keyboard level scaling mode.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ScalingMode {
+    Normal,
+    Fractional,
+}
+
+impl TryFrom<u8> for ScalingMode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ScalingMode::Normal),
+            1 => Ok(ScalingMode::Fractional),
+            _ => Err("Bad scaling mode value, expected 0...1")
+        }
+    }
+}
+
+impl From<ScalingMode> for u8 {
+    fn from(mode: ScalingMode) -> u8 {
+        mode as u8
+    }
+}
+
+impl fmt::Display for ScalingMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            ScalingMode::Normal => "normal",
+            ScalingMode::Fractional => "fractional",
+        })
+    }
+}
+
+/// Pitch EG range.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum PegRange {
+    EightOctaves,
+    TwoOctaves,
+    OneOctave,
+    HalfOctave,
+}
+
+impl TryFrom<u8> for PegRange {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(PegRange::EightOctaves),
+            1 => Ok(PegRange::TwoOctaves),
+            2 => Ok(PegRange::OneOctave),
+            3 => Ok(PegRange::HalfOctave),
+            _ => Err("Bad pitch EG range value, expected 0...3")
+        }
+    }
+}
+
+impl From<PegRange> for u8 {
+    fn from(range: PegRange) -> u8 {
+        range as u8
+    }
+}
+
+impl fmt::Display for PegRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            PegRange::EightOctaves => "8va",
+            PegRange::TwoOctaves => "2va",
+            PegRange::OneOctave => "1va",
+            PegRange::HalfOctave => "1/2va",
+        })
+    }
+}
+
+/// LFO key trigger.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum LfoKeyTrigger {
+    Single,
+    Multi,
+}
+
+impl TryFrom<u8> for LfoKeyTrigger {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(LfoKeyTrigger::Single),
+            1 => Ok(LfoKeyTrigger::Multi),
+            _ => Err("Bad LFO key trigger value, expected 0...1")
+        }
+    }
+}
+
+impl From<LfoKeyTrigger> for u8 {
+    fn from(trigger: LfoKeyTrigger) -> u8 {
+        trigger as u8
+    }
+}
+
+impl fmt::Display for LfoKeyTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            LfoKeyTrigger::Single => "single",
+            LfoKeyTrigger::Multi => "multi",
+        })
+    }
+}
+
+/// Voice polyphony mode of the DX7II.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum VoiceMode {
+    Poly,
+    Mono,
+    UnisonPoly,
+    UnisonMono,
+}
+
+impl TryFrom<u8> for VoiceMode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(VoiceMode::Poly),
+            1 => Ok(VoiceMode::Mono),
+            2 => Ok(VoiceMode::UnisonPoly),
+            3 => Ok(VoiceMode::UnisonMono),
+            _ => Err("Bad voice mode value, expected 0...3")
+        }
+    }
+}
+
+impl From<VoiceMode> for u8 {
+    fn from(mode: VoiceMode) -> u8 {
+        mode as u8
+    }
+}
+
+impl fmt::Display for VoiceMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            VoiceMode::Poly => "poly",
+            VoiceMode::Mono => "mono",
+            VoiceMode::UnisonPoly => "unison poly",
+            VoiceMode::UnisonMono => "unison mono",
+        })
+    }
+}
+
+/// Pitch bend mode.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum BendMode {
+    Low,
+    High,
+    KeyOn,
+    Normal,
+}
+
+impl TryFrom<u8> for BendMode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(BendMode::Low),
+            1 => Ok(BendMode::High),
+            2 => Ok(BendMode::KeyOn),
+            3 => Ok(BendMode::Normal),
+            _ => Err("Bad pitch bend mode value, expected 0...3")
+        }
+    }
+}
+
+impl From<BendMode> for u8 {
+    fn from(mode: BendMode) -> u8 {
+        mode as u8
+    }
+}
+
+impl fmt::Display for BendMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            BendMode::Low => "low",
+            BendMode::High => "high",
+            BendMode::KeyOn => "key on",
+            BendMode::Normal => "normal",
+        })
+    }
+}
+
+/// Modulation depths of a controller.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct Modulation {
+    pub pitch: Level,  // 0 ~ 99
+    pub amplitude: Level,  // 0 ~ 99
+    pub eg_bias: Level,  // 0 ~ 99
+}
+
+impl Modulation {
+    fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        Ok(Self {
+            pitch: context.ranged::<Level>(data, 0, "pitch")?,
+            amplitude: context.ranged::<Level>(data, 1, "amplitude")?,
+            eg_bias: context.ranged::<Level>(data, 2, "eg_bias")?,
+        })
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        vec![self.pitch.encode(), self.amplitude.encode(), self.eg_bias.encode()]
+    }
+}
+
+impl fmt::Display for Modulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pitch = {}, amp = {}, EG bias = {}", self.pitch, self.amplitude, self.eg_bias)
+    }
+}
+
+/// Additional voice data (ACED) of the DX7II and TX802.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Supplement {
+    pub scaling_modes: [ScalingMode; OPERATOR_COUNT],  // OP1 first, like in `Voice`
+    pub amp_mod_sens: [Depth; OPERATOR_COUNT],  // 0 ~ 7, OP1 first
+    pub peg_range: PegRange,
+    pub lfo_key_trigger: LfoKeyTrigger,
+    pub peg_velocity: bool,
+    pub mode: VoiceMode,
+    pub bend_range: BendRange,  // 0 ~ 12
+    pub bend_step: BendStep,  // 0 ~ 12
+    pub bend_mode: BendMode,
+    pub random_pitch: Depth,  // 0 ~ 7
+    pub portamento_mode: PortamentoMode,
+    pub portamento_step: PortamentoStep,  // 0 ~ 12
+    pub portamento_time: Level,  // 0 ~ 99
+    pub mod_wheel: Modulation,
+    pub foot_control: Modulation,
+    pub foot_volume: Level,
+    pub breath_control: Modulation,
+    pub breath_pitch_bias: PitchBias,  // -50 ~ +50
+    pub aftertouch: Modulation,
+    pub aftertouch_pitch_bias: PitchBias,  // -50 ~ +50
+    pub peg_rate_scaling: Depth,  // 0 ~ 7
+    pub foot_control2: Modulation,
+    pub foot_control2_volume: Level,
+    pub midi_control: Modulation,
+    pub midi_control_volume: Level,
+    pub unison_detune: Depth,  // 0 ~ 7
+    pub foot_control_as_cs1: bool,
+}
+
+impl Supplement {
+    /// Makes new additional voice data with the DX7II voice defaults.
+    pub fn new() -> Self {
+        Self {
+            scaling_modes: [ScalingMode::Normal; OPERATOR_COUNT],
+            amp_mod_sens: [Depth::new(0); OPERATOR_COUNT],
+            peg_range: PegRange::EightOctaves,
+            lfo_key_trigger: LfoKeyTrigger::Single,
+            peg_velocity: false,
+            mode: VoiceMode::Poly,
+            bend_range: BendRange::default(),
+            bend_step: BendStep::default(),
+            bend_mode: BendMode::Normal,
+            random_pitch: Depth::new(0),
+            portamento_mode: PortamentoMode::Retain,
+            portamento_step: PortamentoStep::default(),
+            portamento_time: Level::new(0),
+            mod_wheel: Modulation::default(),
+            foot_control: Modulation::default(),
+            foot_volume: Level::new(99),
+            breath_control: Modulation::default(),
+            breath_pitch_bias: PitchBias::default(),
+            aftertouch: Modulation::default(),
+            aftertouch_pitch_bias: PitchBias::default(),
+            peg_rate_scaling: Depth::new(0),
+            foot_control2: Modulation::default(),
+            foot_control2_volume: Level::new(0),
+            midi_control: Modulation::default(),
+            midi_control_volume: Level::new(0),
+            unison_detune: Depth::new(0),
+            foot_control_as_cs1: false,
+        }
+    }
+
+    /// Makes additional voice data from SysEx bytes, handling out-of-range
+    /// values according to the context policy.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, SUPPLEMENT_SIZE, "supplement")?;
+
+        // Like in the voice data, the operators are in reverse order.
+        let mut scaling_modes = [ScalingMode::Normal; OPERATOR_COUNT];
+        let mut amp_mod_sens = [Depth::new(0); OPERATOR_COUNT];
+        for i in 0..OPERATOR_COUNT {
+            let op = OPERATOR_COUNT - i;
+            scaling_modes[op - 1] = context.choice(data, i, &format!("op{}.scaling_mode", op),
+                ScalingMode::try_from, ScalingMode::Normal)?;
+            amp_mod_sens[op - 1] = context.ranged::<Depth>(data, 6 + i, &format!("op{}.amp_mod_sens", op))?;
+        }
+
+        Ok(Self {
+            scaling_modes,
+            amp_mod_sens,
+            peg_range: context.choice(data, 12, "peg_range", PegRange::try_from, PegRange::EightOctaves)?,
+            lfo_key_trigger: context.choice(data, 13, "lfo_key_trigger",
+                LfoKeyTrigger::try_from, LfoKeyTrigger::Single)?,
+            peg_velocity: context.switch(data, 14, "peg_velocity")?,
+            mode: context.choice(data, 15, "mode", VoiceMode::try_from, VoiceMode::Poly)?,
+            bend_range: context.ranged::<BendRange>(data, 16, "bend_range")?,
+            bend_step: context.ranged::<BendStep>(data, 17, "bend_step")?,
+            bend_mode: context.choice(data, 18, "bend_mode", BendMode::try_from, BendMode::Normal)?,
+            random_pitch: context.ranged::<Depth>(data, 19, "random_pitch")?,
+            portamento_mode: context.choice(data, 20, "portamento_mode",
+                PortamentoMode::try_from, PortamentoMode::Retain)?,
+            portamento_step: context.ranged::<PortamentoStep>(data, 21, "portamento_step")?,
+            portamento_time: context.ranged::<Level>(data, 22, "portamento_time")?,
+            mod_wheel: context.nested("mod_wheel", 23, |c| Modulation::parse_with(&data[23..26], c))?,
+            foot_control: context.nested("foot_control", 26, |c| Modulation::parse_with(&data[26..29], c))?,
+            foot_volume: context.ranged::<Level>(data, 29, "foot_volume")?,
+            breath_control: context.nested("breath_control", 30, |c| Modulation::parse_with(&data[30..33], c))?,
+            breath_pitch_bias: context.ranged::<PitchBias>(data, 33, "breath_pitch_bias")?,
+            aftertouch: context.nested("aftertouch", 34, |c| Modulation::parse_with(&data[34..37], c))?,
+            aftertouch_pitch_bias: context.ranged::<PitchBias>(data, 37, "aftertouch_pitch_bias")?,
+            peg_rate_scaling: context.ranged::<Depth>(data, 38, "peg_rate_scaling")?,
+            foot_control2: context.nested("foot_control2", 39, |c| Modulation::parse_with(&data[39..42], c))?,
+            foot_control2_volume: context.ranged::<Level>(data, 42, "foot_control2_volume")?,
+            midi_control: context.nested("midi_control", 43, |c| Modulation::parse_with(&data[43..46], c))?,
+            midi_control_volume: context.ranged::<Level>(data, 46, "midi_control_volume")?,
+            unison_detune: context.ranged::<Depth>(data, 47, "unison_detune")?,
+            foot_control_as_cs1: context.switch(data, 48, "foot_control_as_cs1")?,
+        })
+    }
+
+    /// Packs the additional voice data for use in an AMEM bank.
+    pub fn pack(data: &[u8]) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::new();
+
+        // Scaling modes, one bit per operator
+        let mut byte0: u8 = 0;
+        for (i, b) in data[..OPERATOR_COUNT].iter().enumerate() {
+            byte0.set_bit(i, *b != 0);
+        }
+        result.push(byte0);
+
+        // AM sensitivities, two operators per byte
+        result.push(data[6] | (data[7] << 3));
+        result.push(data[8] | (data[9] << 3));
+        result.push(data[10] | (data[11] << 3));
+
+        let mut byte4: u8 = data[12];  // PEG range
+        byte4.set_bit_range(2..3, data[13]);  // LFO key trigger
+        byte4.set_bit_range(3..4, data[14]);  // PEG velocity switch
+        byte4.set_bit_range(4..6, data[15]);  // voice mode
+        result.push(byte4);
+
+        result.push(data[16]);  // pitch bend range
+        result.push(data[17]);  // pitch bend step
+        result.push(data[18] | (data[19] << 2));  // pitch bend mode + random pitch
+        result.push(data[20] | (data[21] << 1));  // portamento mode + step
+        result.push(data[22]);  // portamento time
+
+        // Controller settings are copied as is.
+        result.extend(&data[23..47]);
+
+        result.push(data[47] | (data[48] << 3));  // unison detune + FC1 as CS1
+
+        assert_eq!(result.len(), SUPPLEMENT_PACKED_SIZE);
+        result
+    }
+
+    /// Unpacks additional voice data from an AMEM bank.
+    /// Returns a vector to use for normal parsing.
+    pub fn unpack(data: &[u8]) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::new();
+
+        for i in 0..OPERATOR_COUNT {
+            result.push(if data[0].bit(i) { 1 } else { 0 });  // scaling modes
+        }
+
+        for b in &data[1..4] {
+            result.push(b.bit_range(0..3));  // AM sensitivities
+            result.push(b.bit_range(3..6));
+        }
+
+        result.push(data[4].bit_range(0..2));  // PEG range
+        result.push(data[4].bit_range(2..3));  // LFO key trigger
+        result.push(data[4].bit_range(3..4));  // PEG velocity switch
+        result.push(data[4].bit_range(4..6));  // voice mode
+
+        result.push(data[5]);  // pitch bend range
+        result.push(data[6]);  // pitch bend step
+        result.push(data[7].bit_range(0..2));  // pitch bend mode
+        result.push(data[7].bit_range(2..5));  // random pitch
+        result.push(data[8].bit_range(0..1));  // portamento mode
+        result.push(data[8].bit_range(1..5));  // portamento step
+        result.push(data[9]);  // portamento time
+
+        result.extend(&data[10..34]);
+
+        result.push(data[34].bit_range(0..3));  // unison detune
+        result.push(data[34].bit_range(3..4));  // FC1 as CS1
+
+        assert_eq!(result.len(), SUPPLEMENT_SIZE);
+        result
+    }
+}
+
+impl Default for Supplement {
+    fn default() -> Self {
+        Supplement::new()
+    }
+}
+
+impl SystemExclusiveData for Supplement {
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+
+        for i in (0..OPERATOR_COUNT).rev() {  // NOTE: reverse order!
+            data.push(self.scaling_modes[i].into());
+        }
+        for i in (0..OPERATOR_COUNT).rev() {
+            data.push(self.amp_mod_sens[i].encode());
+        }
+
+        data.push(self.peg_range.into());
+        data.push(self.lfo_key_trigger.into());
+        data.push(if self.peg_velocity { 1 } else { 0 });
+        data.push(self.mode.into());
+        data.push(self.bend_range.encode());
+        data.push(self.bend_step.encode());
+        data.push(self.bend_mode.into());
+        data.push(self.random_pitch.encode());
+        data.push(self.portamento_mode.into());
+        data.push(self.portamento_step.encode());
+        data.push(self.portamento_time.encode());
+        data.extend(self.mod_wheel.to_bytes());
+        data.extend(self.foot_control.to_bytes());
+        data.push(self.foot_volume.encode());
+        data.extend(self.breath_control.to_bytes());
+        data.push(self.breath_pitch_bias.encode());
+        data.extend(self.aftertouch.to_bytes());
+        data.push(self.aftertouch_pitch_bias.encode());
+        data.push(self.peg_rate_scaling.encode());
+        data.extend(self.foot_control2.to_bytes());
+        data.push(self.foot_control2_volume.encode());
+        data.extend(self.midi_control.to_bytes());
+        data.push(self.midi_control_volume.encode());
+        data.push(self.unison_detune.encode());
+        data.push(if self.foot_control_as_cs1 { 1 } else { 0 });
+
+        assert_eq!(data.len(), SUPPLEMENT_SIZE);
+
+        data
+    }
+
+    fn data_size() -> usize { SUPPLEMENT_SIZE }
+}
+
+impl fmt::Display for Supplement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scaling_modes: Vec<String> = self.scaling_modes.iter().map(|m| m.to_string()).collect();
+        let amp_mod_sens: Vec<String> = self.amp_mod_sens.iter().map(|s| s.to_string()).collect();
+        write!(f, "Scaling modes: {}
+AM sensitivities: {}
+PEG: range = {}, velocity = {}, rate scaling = {}
+LFO key trigger: {}
+Mode: {}, unison detune = {}
+Pitch bend: range = {}, step = {}, mode = {}
+Random pitch: {}
+Portamento: mode = {}, step = {}, time = {}
+Mod wheel: {}
+Foot control: {}, volume = {}
+Breath control: {}, pitch bias = {}
+Aftertouch: {}, pitch bias = {}
+Foot control 2: {}, volume = {}
+MIDI control: {}, volume = {}",
+            scaling_modes.join(" "),
+            amp_mod_sens.join(" "),
+            self.peg_range, self.peg_velocity, self.peg_rate_scaling,
+            self.lfo_key_trigger,
+            self.mode, self.unison_detune,
+            self.bend_range, self.bend_step, self.bend_mode,
+            self.random_pitch,
+            self.portamento_mode, self.portamento_step, self.portamento_time,
+            self.mod_wheel,
+            self.foot_control, self.foot_volume,
+            self.breath_control, self.breath_pitch_bias,
+            self.aftertouch, self.aftertouch_pitch_bias,
+            self.foot_control2, self.foot_control2_volume,
+            self.midi_control, self.midi_control_volume)
+    }
+}
+
+/// AMEM bank of 32 supplements, accompanying a 32-voice cartridge.
+#[derive(Debug, Clone)]
+pub struct SupplementBank {
+    pub supplements: Vec<Supplement>,
+}
+
+impl SupplementBank {
+    /// Makes a supplement bank from SysEx bytes, handling out-of-range values
+    /// according to the context policy.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, SUPPLEMENT_BANK_SIZE, "supplement bank")?;
+
+        let mut supplements = Vec::<Supplement>::new();
+        for (i, packed_data) in data.chunks(SUPPLEMENT_PACKED_SIZE).take(SUPPLEMENT_COUNT).enumerate() {
+            context.voice = Some(i);
+            let result = Supplement::parse_with(&Supplement::unpack(packed_data), context);
+            context.voice = None;
+
+            // The offsets in unpacked data don't match the packed data,
+            // so report errors at the start of the packed supplement.
+            let offset = i * SUPPLEMENT_PACKED_SIZE;
+            supplements.push(result.map_err(|e| match e {
+                ParseError::InvalidData(_, message) =>
+                    ParseError::InvalidData(offset as u32, format!("supplements[{}].{}", i, message)),
+                other => other,
+            })?);
+        }
+        Ok(SupplementBank { supplements })
+    }
+
+    /// Combines the supplements with the voices of a cartridge.
+    pub fn combine(&self, cartridge: &Cartridge) -> Vec<ExtendedVoice> {
+        cartridge.voices.iter().zip(self.supplements.iter())
+            .map(|(voice, supplement)| ExtendedVoice {
+                voice: voice.clone(),
+                supplement: *supplement,
+            })
+            .collect()
+    }
+}
+
+impl Default for SupplementBank {
+    fn default() -> Self {
+        SupplementBank {
+            supplements: vec![Default::default(); SUPPLEMENT_COUNT],
+        }
+    }
+}
+
+impl SystemExclusiveData for SupplementBank {
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for supplement in &self.supplements {
+            data.extend(Supplement::pack(&supplement.to_bytes()));
+        }
+        data
+    }
+
+    fn data_size() -> usize { SUPPLEMENT_BANK_SIZE }
+}
+
+/// A DX7 voice with the DX7II / TX802 additional voice data.
+#[derive(Debug, Clone)]
+pub struct ExtendedVoice {
+    pub voice: Voice,
+    pub supplement: Supplement,
+}
+
+impl ExtendedVoice {
+    /// Makes the bulk dumps for this voice, in the order that the DX7II
+    /// sends them: first the additional voice data, then the voice.
+    pub fn to_messages(&self, channel: MidiChannel) -> Vec<Message> {
+        vec![
+            Message::supplement(channel, self.supplement),
+            Message::voice(channel, self.voice.clone()),
+        ]
+    }
+}
+
+impl fmt::Display for ExtendedVoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n{}", self.voice, self.supplement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_supplement() -> Supplement {
+        let mut supplement = Supplement {
+            peg_range: PegRange::OneOctave,
+            lfo_key_trigger: LfoKeyTrigger::Multi,
+            peg_velocity: true,
+            mode: VoiceMode::UnisonMono,
+            bend_mode: BendMode::KeyOn,
+            random_pitch: Depth::new(5),
+            portamento_mode: PortamentoMode::Follow,
+            portamento_step: PortamentoStep::new(12),
+            breath_pitch_bias: PitchBias::new(-50),
+            unison_detune: Depth::new(7),
+            foot_control_as_cs1: true,
+            ..Supplement::new()
+        };
+        supplement.scaling_modes[0] = ScalingMode::Fractional;
+        supplement.amp_mod_sens[5] = Depth::new(7);
+        supplement
+    }
+
+    #[test]
+    fn test_supplement_round_trip() {
+        let supplement = make_supplement();
+        let data = supplement.to_bytes();
+        assert_eq!(data.len(), SUPPLEMENT_SIZE);
+        assert_eq!(data[5], 1);  // OP1 scaling mode is last
+        assert_eq!(data[6], 7);  // OP6 AM sensitivity is first
+        assert_eq!(data[33], 0);  // pitch bias -50
+        assert_eq!(Supplement::parse(&data).unwrap(), supplement);
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let data = make_supplement().to_bytes();
+        let packed = Supplement::pack(&data);
+        assert_eq!(packed.len(), SUPPLEMENT_PACKED_SIZE);
+        assert_eq!(Supplement::unpack(&packed), data);
+    }
+
+    #[test]
+    fn test_bank_round_trip() {
+        let mut bank = SupplementBank::default();
+        bank.supplements[31] = make_supplement();
+        let data = bank.to_bytes();
+        assert_eq!(data.len(), SUPPLEMENT_BANK_SIZE);
+        let parsed = SupplementBank::parse(&data).unwrap();
+        assert_eq!(parsed.supplements, bank.supplements);
+    }
+}
@@ -4,19 +4,71 @@ use std::convert::{
 };
 
 use std::fmt;
+use std::io::{
+    self,
+    Read,
+};
 use syxpack::{
     Ranged,
     ParseError,
     MidiChannel,
     Encoding,
     SystemExclusiveData,
+    Manufacturer,
+    INITIATOR,
+    TERMINATOR,
 };
 
-#[derive(Debug, Clone, Copy)]
+use crate::dx7::{
+    check_length,
+    invalid_field,
+    nested_error,
+};
+use crate::dx7::voice::{
+    Voice,
+    VOICE_SIZE,
+};
+use crate::dx7::policy::ParseContext;
+use crate::dx7::cartridge::{
+    Cartridge,
+    CARTRIDGE_DATA_SIZE,
+};
+use crate::dx7::supplement::{
+    Supplement,
+    SupplementBank,
+    SUPPLEMENT_SIZE,
+    SUPPLEMENT_BANK_SIZE,
+};
+use crate::dx7::performance::{
+    Performance,
+    PerformanceBank,
+    PERFORMANCE_SIZE,
+    PERFORMANCE_BANK_SIZE,
+};
+use crate::dx7::tx802;
+use crate::dx7::tuning::{
+    Tuning,
+    OCTAVE_TUNING_SIZE,
+    KEYBOARD_TUNING_SIZE,
+};
+
+/// Yamaha manufacturer ID.
+pub const YAMAHA: u8 = 0x43;
+
+/// Size of the DX7 bulk dump header, excluding the manufacturer ID.
+pub const HEADER_SIZE: usize = 4;
+
+/// Size of the classification name at the start of a universal bulk dump.
+pub const CLASSIFICATION_SIZE: usize = 10;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 #[repr(u8)]
 pub enum Format {
     Voice = 0,
+    Supplement = 5,  // DX7II / TX802 additional voice data (ACED)
+    SupplementBank = 6,  // 32 supplements (AMEM)
     Cartridge = 9,
+    Universal = 0x7e,  // data identified by a classification name
 }
 
 impl fmt::Display for Format {
@@ -25,7 +77,10 @@ impl fmt::Display for Format {
             f, "{}",
             match *self {
                 Format::Voice => "voice",
-                Format::Cartridge => "cartridge"
+                Format::Supplement => "supplement",
+                Format::SupplementBank => "supplement bank",
+                Format::Cartridge => "cartridge",
+                Format::Universal => "universal"
             })
     }
 }
@@ -36,18 +91,102 @@ impl TryFrom<u8> for Format {
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
             0 => Ok(Format::Voice),
+            5 => Ok(Format::Supplement),
+            6 => Ok(Format::SupplementBank),
             9 => Ok(Format::Cartridge),
+            0x7e => Ok(Format::Universal),
             _ => Err("Bad format value")
         }
     }
 }
 
+impl Format {
+    /// Gets the number of payload bytes in a bulk dump of this format.
+    /// For a universal bulk dump this is only the classification name;
+    /// the size of the data that follows depends on the classification.
+    pub fn data_size(&self) -> usize {
+        match *self {
+            Format::Voice => VOICE_SIZE,
+            Format::Supplement => SUPPLEMENT_SIZE,
+            Format::SupplementBank => SUPPLEMENT_BANK_SIZE,
+            Format::Cartridge => CARTRIDGE_DATA_SIZE,
+            Format::Universal => CLASSIFICATION_SIZE,
+        }
+    }
+}
+
 impl From<Format> for u8 {
     fn from(f: Format) -> u8 {
         f as u8
     }
 }
 
+/// Classification of the data in a universal bulk dump.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Classification {
+    Performance,  // DX7II PCED
+    PerformanceBank,  // DX7II PMEM
+    Tx802Performance,  // TX802 PCED
+    Tx802PerformanceBank,  // TX802 PMEM
+    OctaveTuning,  // DX7II micro tuning, one octave
+    KeyboardTuning,  // DX7II micro tuning, full keyboard
+}
+
+impl Classification {
+    /// Gets the ten-character classification name.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Classification::Performance => "LM  8973PE",
+            Classification::PerformanceBank => "LM  8973PM",
+            Classification::Tx802Performance => "LM  8952PE",
+            Classification::Tx802PerformanceBank => "LM  8952PM",
+            Classification::OctaveTuning => "LM  MCRTE0",
+            Classification::KeyboardTuning => "LM  MCRTE1",
+        }
+    }
+
+    /// Gets the number of data bytes following the classification name.
+    pub fn data_size(&self) -> usize {
+        match *self {
+            Classification::Performance => PERFORMANCE_SIZE,
+            Classification::PerformanceBank => PERFORMANCE_BANK_SIZE,
+            Classification::Tx802Performance => tx802::PERFORMANCE_SIZE,
+            Classification::Tx802PerformanceBank => tx802::PERFORMANCE_BANK_SIZE,
+            Classification::OctaveTuning => OCTAVE_TUNING_SIZE,
+            Classification::KeyboardTuning => KEYBOARD_TUNING_SIZE,
+        }
+    }
+
+    /// Gets all the supported classifications.
+    pub fn all() -> Vec<Classification> {
+        vec![
+            Classification::Performance,
+            Classification::PerformanceBank,
+            Classification::Tx802Performance,
+            Classification::Tx802PerformanceBank,
+            Classification::OctaveTuning,
+            Classification::KeyboardTuning,
+        ]
+    }
+}
+
+impl TryFrom<&[u8]> for Classification {
+    type Error = &'static str;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Classification::all().into_iter()
+            .find(|c| c.name().as_bytes() == value)
+            .ok_or("Unknown classification name")
+    }
+}
+
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Header {
     pub sub_status: u8,  // 0=voice/cartridge, 1=parameter
     pub channel: MidiChannel,
@@ -57,6 +196,27 @@ pub struct Header {
     // cartridge=4096 (01000000000000 = 0x1000, appears as "20 00")
 }
 
+impl Header {
+    /// Makes a new bulk dump header for the given channel and format.
+    pub fn new(channel: MidiChannel, format: Format) -> Self {
+        Self {
+            sub_status: 0,
+            channel,
+            format,
+            byte_count: format.data_size() as u16,
+        }
+    }
+
+    /// Makes a new universal bulk dump header for the given channel
+    /// and classification.
+    pub fn universal(channel: MidiChannel, classification: Classification) -> Self {
+        Self {
+            byte_count: (CLASSIFICATION_SIZE + classification.data_size()) as u16,
+            ..Header::new(channel, Format::Universal)
+        }
+    }
+}
+
 impl fmt::Display for Header {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -68,24 +228,22 @@ impl fmt::Display for Header {
 
 impl SystemExclusiveData for Header {
     fn parse(data: &[u8]) -> Result<Self, ParseError> {
-        //let byte_count_msb = data[2];
-        //let byte_count_lsb = data[3];
+        check_length(data, HEADER_SIZE, "header")?;
+
         let channel = ((data[0] & 0b00001111) + 1) as i32;
         if !MidiChannel::contains(channel) {
             return Err(ParseError::InvalidData(
                 1, // offset of value
                 format!("Invalid MIDI channel value (raw: {:02X})", data[0])));
         }
-        let format = Format::try_from(data[1]).expect("format should be valid");
+        let format = Format::try_from(data[1])
+            .map_err(|e| invalid_field(1, "format", e))?;
 
         Ok(Self {
             sub_status: (data[0] >> 4) & 0b00000111,
             channel: MidiChannel::new(channel),
             format,
-            byte_count: match format {
-                Format::Voice => crate::dx7::voice::VOICE_SIZE as u16,
-                Format::Cartridge => crate::dx7::cartridge::CARTRIDGE_DATA_SIZE as u16,
-            }
+            byte_count: ((data[2] as u16) << 7) | (data[3] as u16),
         })
     }
 
@@ -97,21 +255,13 @@ impl SystemExclusiveData for Header {
 
         result.push(self.format.into());
 
-        match self.format {
-            Format::Voice => {
-                result.push(0x01);
-                result.push(0x1B);
-            },
-            Format::Cartridge => {
-                result.push(0x20);
-                result.push(0x00);
-            }
-        }
+        result.push(((self.byte_count >> 7) & 0x7f) as u8);
+        result.push((self.byte_count & 0x7f) as u8);
 
         result
     }
 
-    fn data_size() -> usize { 4 }
+    fn data_size() -> usize { HEADER_SIZE }
 }
 
 pub fn checksum(data: &[u8]) -> u8 {
@@ -122,6 +272,393 @@ pub fn checksum(data: &[u8]) -> u8 {
     checksum as u8
 }
 
+/// Payload of a DX7 bulk dump.
+#[derive(Debug, Clone)]
+pub enum Payload {
+    Voice(Box<Voice>),
+    Supplement(Box<Supplement>),
+    SupplementBank(SupplementBank),
+    Cartridge(Cartridge),
+    Performance(Box<Performance>),
+    PerformanceBank(PerformanceBank),
+    Tx802Performance(Box<tx802::Performance>),
+    Tx802PerformanceBank(tx802::PerformanceBank),
+    Tuning(Tuning),
+}
+
+impl Payload {
+    /// Gets the format matching this payload.
+    pub fn format(&self) -> Format {
+        match self {
+            Payload::Voice(_) => Format::Voice,
+            Payload::Supplement(_) => Format::Supplement,
+            Payload::SupplementBank(_) => Format::SupplementBank,
+            Payload::Cartridge(_) => Format::Cartridge,
+            Payload::Performance(_)
+                | Payload::PerformanceBank(_)
+                | Payload::Tx802Performance(_)
+                | Payload::Tx802PerformanceBank(_)
+                | Payload::Tuning(_) => Format::Universal,
+        }
+    }
+
+    /// Gets the classification of a universal bulk dump payload.
+    pub fn classification(&self) -> Option<Classification> {
+        match self {
+            Payload::Performance(_) => Some(Classification::Performance),
+            Payload::PerformanceBank(_) => Some(Classification::PerformanceBank),
+            Payload::Tx802Performance(_) => Some(Classification::Tx802Performance),
+            Payload::Tx802PerformanceBank(_) => Some(Classification::Tx802PerformanceBank),
+            Payload::Tuning(Tuning::Octave(_)) => Some(Classification::OctaveTuning),
+            Payload::Tuning(Tuning::Keyboard(_)) => Some(Classification::KeyboardTuning),
+            _ => None,
+        }
+    }
+}
+
+/// A complete DX7 bulk dump, from the System Exclusive initiator
+/// to the terminator.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub header: Header,
+    pub payload: Payload,
+}
+
+impl Message {
+    /// Makes a single voice bulk dump.
+    pub fn voice(channel: MidiChannel, voice: Voice) -> Self {
+        Self {
+            header: Header::new(channel, Format::Voice),
+            payload: Payload::Voice(Box::new(voice)),
+        }
+    }
+
+    /// Makes an additional voice data (ACED) bulk dump.
+    pub fn supplement(channel: MidiChannel, supplement: Supplement) -> Self {
+        Self {
+            header: Header::new(channel, Format::Supplement),
+            payload: Payload::Supplement(Box::new(supplement)),
+        }
+    }
+
+    /// Makes a 32-supplement (AMEM) bulk dump.
+    pub fn supplement_bank(channel: MidiChannel, bank: SupplementBank) -> Self {
+        Self {
+            header: Header::new(channel, Format::SupplementBank),
+            payload: Payload::SupplementBank(bank),
+        }
+    }
+
+    /// Makes a 32-voice cartridge bulk dump.
+    pub fn cartridge(channel: MidiChannel, cartridge: Cartridge) -> Self {
+        Self {
+            header: Header::new(channel, Format::Cartridge),
+            payload: Payload::Cartridge(cartridge),
+        }
+    }
+
+    /// Makes a DX7II performance (PCED) bulk dump.
+    pub fn performance(channel: MidiChannel, performance: Performance) -> Self {
+        Self {
+            header: Header::universal(channel, Classification::Performance),
+            payload: Payload::Performance(Box::new(performance)),
+        }
+    }
+
+    /// Makes a DX7II 32-performance (PMEM) bulk dump.
+    pub fn performance_bank(channel: MidiChannel, bank: PerformanceBank) -> Self {
+        Self {
+            header: Header::universal(channel, Classification::PerformanceBank),
+            payload: Payload::PerformanceBank(bank),
+        }
+    }
+
+    /// Makes a TX802 performance (PCED) bulk dump.
+    pub fn tx802_performance(channel: MidiChannel, performance: tx802::Performance) -> Self {
+        Self {
+            header: Header::universal(channel, Classification::Tx802Performance),
+            payload: Payload::Tx802Performance(Box::new(performance)),
+        }
+    }
+
+    /// Makes a TX802 32-performance (PMEM) bulk dump.
+    pub fn tx802_performance_bank(channel: MidiChannel, bank: tx802::PerformanceBank) -> Self {
+        Self {
+            header: Header::universal(channel, Classification::Tx802PerformanceBank),
+            payload: Payload::Tx802PerformanceBank(bank),
+        }
+    }
+
+    /// Makes a DX7II micro tuning bulk dump.
+    pub fn tuning(channel: MidiChannel, tuning: Tuning) -> Self {
+        let payload = Payload::Tuning(tuning);
+        let classification = payload.classification().expect("tuning classification");
+        Self {
+            header: Header::universal(channel, classification),
+            payload,
+        }
+    }
+}
+
+impl Message {
+    /// Parses a complete bulk dump, verifying the byte count and checksum,
+    /// and handling out-of-range values according to the context policy.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        // initiator, manufacturer, header, checksum, terminator
+        let overhead = 2 + HEADER_SIZE + 2;
+        if data.len() < overhead {
+            return Err(ParseError::InvalidLength(data.len(), overhead));
+        }
+
+        if data[0] != INITIATOR || data[data.len() - 1] != TERMINATOR {
+            return Err(ParseError::InvalidMessage);
+        }
+
+        if data[1] != YAMAHA {
+            return Err(ParseError::InvalidManufacturer);
+        }
+
+        let header = Header::parse(&data[2..2 + HEADER_SIZE])?;
+        let start = 2 + HEADER_SIZE;
+
+        // A universal bulk dump starts with the classification name,
+        // which determines the size of the rest of the data.
+        let classification = if header.format == Format::Universal {
+            if data.len() < overhead + CLASSIFICATION_SIZE {
+                return Err(ParseError::InvalidLength(data.len(), overhead + CLASSIFICATION_SIZE));
+            }
+            Some(Classification::try_from(&data[start..start + CLASSIFICATION_SIZE])
+                .map_err(|e| invalid_field(start, "classification", e))?)
+        } else {
+            None
+        };
+        let expected_size = match classification {
+            Some(c) => CLASSIFICATION_SIZE + c.data_size(),
+            None => header.format.data_size(),
+        };
+        if header.byte_count as usize != expected_size {
+            return Err(ParseError::InvalidData(
+                4,  // offset of byte count MSB
+                format!("Invalid byte count {} for {}, expected {}",
+                    header.byte_count, header.format, expected_size)));
+        }
+
+        let actual_size = data.len() - overhead;
+        if actual_size != expected_size {
+            return Err(ParseError::InvalidLength(actual_size, expected_size));
+        }
+
+        let payload_data = &data[start..start + expected_size];
+        let expected_checksum = data[start + expected_size];
+        let actual_checksum = checksum(payload_data);
+        if actual_checksum != expected_checksum {
+            return Err(ParseError::InvalidChecksum(actual_checksum, expected_checksum));
+        }
+
+        let payload = match header.format {
+            Format::Voice => Voice::parse_with(payload_data, context)
+                .map(|voice| Payload::Voice(Box::new(voice))),
+            Format::Supplement => Supplement::parse_with(payload_data, context)
+                .map(|supplement| Payload::Supplement(Box::new(supplement))),
+            Format::SupplementBank => SupplementBank::parse_with(payload_data, context)
+                .map(Payload::SupplementBank),
+            Format::Cartridge => Cartridge::parse_with(payload_data, context)
+                .map(Payload::Cartridge),
+            Format::Universal => {
+                let universal_data = &payload_data[CLASSIFICATION_SIZE..];
+                match classification.expect("classification for universal bulk dump") {
+                    Classification::Performance => Performance::parse_with(universal_data, context)
+                        .map(|performance| Payload::Performance(Box::new(performance))),
+                    Classification::PerformanceBank => PerformanceBank::parse_with(universal_data, context)
+                        .map(Payload::PerformanceBank),
+                    Classification::Tx802Performance => tx802::Performance::parse_with(universal_data, context)
+                        .map(|performance| Payload::Tx802Performance(Box::new(performance))),
+                    Classification::Tx802PerformanceBank => tx802::PerformanceBank::parse_with(universal_data, context)
+                        .map(Payload::Tx802PerformanceBank),
+                    Classification::OctaveTuning
+                        | Classification::KeyboardTuning => Tuning::parse_with(universal_data, context)
+                        .map(Payload::Tuning),
+                }.map_err(|e| nested_error(e, CLASSIFICATION_SIZE, ""))
+            },
+        }.map_err(|e| nested_error(e, start, ""))?;
+
+        Ok(Self { header, payload })
+    }
+}
+
+// `Message` does not implement `SystemExclusiveData`, because the size
+// of a complete message depends on its payload.
+impl Message {
+    /// Parses a complete bulk dump, verifying the byte count and checksum.
+    pub fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
+
+    /// Gets the complete message bytes, including the generated checksum.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let payload_data = match &self.payload {
+            Payload::Voice(voice) => voice.to_bytes(),
+            Payload::Supplement(supplement) => supplement.to_bytes(),
+            Payload::SupplementBank(bank) => bank.to_bytes(),
+            Payload::Cartridge(cartridge) => cartridge.to_bytes(),
+            Payload::Performance(performance) => performance.to_bytes(),
+            Payload::PerformanceBank(bank) => bank.to_bytes(),
+            Payload::Tx802Performance(performance) => performance.to_bytes(),
+            Payload::Tx802PerformanceBank(bank) => bank.to_bytes(),
+            Payload::Tuning(tuning) => tuning.to_bytes(),
+        };
+
+        // A universal bulk dump payload starts with the classification name.
+        let payload_data = match self.payload.classification() {
+            Some(classification) => [classification.name().as_bytes(), &payload_data].concat(),
+            None => payload_data,
+        };
+
+        let header = Header {
+            format: self.payload.format(),
+            byte_count: payload_data.len() as u16,
+            ..self.header
+        };
+
+        let mut result = vec![INITIATOR, YAMAHA];
+        result.extend(header.to_bytes());
+        let checksum = checksum(&payload_data);
+        result.extend(payload_data);
+        result.push(checksum);
+        result.push(TERMINATOR);
+        result
+    }
+}
+
+/// Reason for skipping a part of a System Exclusive file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SkipReason {
+    Garbage,  // bytes outside of any System Exclusive message
+    Unterminated,  // message without a terminator
+    OtherManufacturer(Manufacturer),
+    UnsupportedFormat(u8),  // Yamaha message that is not a supported bulk dump
+    Invalid(ParseError),  // bulk dump that failed to parse
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SkipReason::Garbage => write!(f, "not a System Exclusive message"),
+            SkipReason::Unterminated => write!(f, "message has no terminator"),
+            SkipReason::OtherManufacturer(manufacturer) =>
+                write!(f, "message from another manufacturer ({})", manufacturer.to_hex()),
+            SkipReason::UnsupportedFormat(b) =>
+                write!(f, "unsupported Yamaha message (format byte {:02X}H)", b),
+            SkipReason::Invalid(e) => write!(f, "invalid bulk dump: {}", e),
+        }
+    }
+}
+
+/// An entry found when scanning a System Exclusive file.
+#[derive(Debug, Clone)]
+pub enum Entry {
+    /// A DX7 bulk dump starting at `offset`.
+    Message { offset: usize, message: Message },
+
+    /// A part of the data that was skipped, and why.
+    Skipped { offset: usize, length: usize, reason: SkipReason },
+}
+
+/// Scans `data` for System Exclusive messages, and parses the Yamaha
+/// bulk dumps among them: DX7 voices and cartridges, DX7II additional
+/// voice data (ACED/AMEM), performances (PCED/PMEM) and micro tunings,
+/// and TX802 performances. Everything else is reported as skipped.
+/// The entries are in the order they appear in the data.
+pub fn scan(data: &[u8]) -> Vec<Entry> {
+    scan_with(data, &mut ParseContext::lenient())
+}
+
+/// Scans `data` like `scan`, parsing the bulk dumps with
+/// the policy of the given context.
+pub fn scan_with(data: &[u8], context: &mut ParseContext) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        // Anything before the next initiator is garbage.
+        let start = match data[offset..].iter().position(|&b| b == INITIATOR) {
+            Some(position) => offset + position,
+            None => data.len(),
+        };
+        if start > offset {
+            entries.push(Entry::Skipped {
+                offset,
+                length: start - offset,
+                reason: SkipReason::Garbage,
+            });
+        }
+        if start == data.len() {
+            break;
+        }
+
+        // The message ends at the terminator. If another initiator
+        // or the end of data comes first, the message is truncated.
+        let end = data[start + 1..].iter()
+            .position(|&b| b == TERMINATOR || b == INITIATOR)
+            .map(|position| start + 1 + position);
+        match end {
+            Some(end) if data[end] == TERMINATOR => {
+                let message_data = &data[start..=end];
+                entries.push(match identify(message_data, context) {
+                    Ok(message) => Entry::Message { offset: start, message },
+                    Err(reason) => Entry::Skipped {
+                        offset: start,
+                        length: message_data.len(),
+                        reason,
+                    },
+                });
+                offset = end + 1;
+            },
+            _ => {
+                let next = end.unwrap_or(data.len());
+                entries.push(Entry::Skipped {
+                    offset: start,
+                    length: next - start,
+                    reason: SkipReason::Unterminated,
+                });
+                offset = next;
+            }
+        }
+    }
+
+    entries
+}
+
+/// Reads all the data from `reader` and scans it like `scan`.
+pub fn read<R: Read>(mut reader: R) -> io::Result<Vec<Entry>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(scan(&data))
+}
+
+// Identifies and parses one complete System Exclusive message.
+fn identify(data: &[u8], context: &mut ParseContext) -> Result<Message, SkipReason> {
+    if data.len() < 3 {
+        return Err(SkipReason::Invalid(ParseError::InvalidMessage));
+    }
+
+    if data[1] != YAMAHA {
+        let manufacturer = if data[1] == 0x00 && data.len() >= 5 {
+            Manufacturer::Extended([data[1], data[2], data[3]])
+        } else {
+            Manufacturer::Standard(data[1])
+        };
+        return Err(SkipReason::OtherManufacturer(manufacturer));
+    }
+
+    // Only bulk dumps (sub-status 0) of the known formats are supported.
+    if data.len() < 4 || (data[2] >> 4) != 0 || Format::try_from(data[3]).is_err() {
+        return Err(SkipReason::UnsupportedFormat(if data.len() >= 4 { data[3] } else { data[2] }));
+    }
+
+    Message::parse_with(data, context).map_err(SkipReason::Invalid)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -141,4 +678,139 @@ mod tests {
         let cs = checksum(data);
         assert_eq!(0x33, cs);
     }
+
+    fn make_rom1a_message() -> Vec<u8> {
+        let mut data = vec![INITIATOR, YAMAHA];
+        data.extend(include_bytes!("rom1a_payload.dat"));
+        data.push(TERMINATOR);
+        data
+    }
+
+    #[test]
+    fn test_parse_cartridge_message() {
+        let data = make_rom1a_message();
+        let message = Message::parse(&data).expect("valid cartridge message");
+        assert_eq!(message.header.format, Format::Cartridge);
+        assert_eq!(message.header.byte_count, 4096);
+        match message.payload {
+            Payload::Cartridge(cartridge) => {
+                assert_eq!(cartridge.voices.len(), 32);
+                assert_eq!(cartridge.voices[0].name.value(), "BRASS   1 ");
+            },
+            _ => panic!("expected a cartridge"),
+        }
+    }
+
+    #[test]
+    fn test_cartridge_message_round_trip() {
+        let data = make_rom1a_message();
+        let message = Message::parse(&data).expect("valid cartridge message");
+        assert_eq!(message.to_bytes(), data);
+    }
+
+    #[test]
+    fn test_voice_message_round_trip() {
+        let message = Message::voice(MidiChannel::new(1), Voice::new());
+        let data = message.to_bytes();
+        assert_eq!(data.len(), 2 + HEADER_SIZE + VOICE_SIZE + 2);
+        assert_eq!(&data[..6], &[0xF0, 0x43, 0x00, 0x00, 0x01, 0x1B]);
+
+        let parsed = Message::parse(&data).expect("valid voice message");
+        assert_eq!(parsed.to_bytes(), data);
+    }
+
+    #[test]
+    fn test_supplement_bank_message_round_trip() {
+        let message = Message::supplement_bank(MidiChannel::new(1), SupplementBank::default());
+        let data = message.to_bytes();
+        assert_eq!(&data[..6], &[0xF0, 0x43, 0x00, 0x06, 0x08, 0x60]);
+
+        let parsed = Message::parse(&data).expect("valid supplement bank message");
+        assert_eq!(parsed.header.format, Format::SupplementBank);
+        assert_eq!(parsed.to_bytes(), data);
+    }
+
+    #[test]
+    fn test_performance_message_round_trip() {
+        let message = Message::performance(MidiChannel::new(1), Performance::new());
+        let data = message.to_bytes();
+        assert_eq!(&data[..6], &[0xF0, 0x43, 0x00, 0x7E, 0x00, 0x3D]);
+        assert_eq!(&data[6..16], b"LM  8973PE");
+
+        let parsed = Message::parse(&data).expect("valid performance message");
+        assert_eq!(parsed.payload.classification(), Some(Classification::Performance));
+        assert_eq!(parsed.to_bytes(), data);
+    }
+
+    #[test]
+    fn test_unknown_classification() {
+        let mut data = Message::performance(MidiChannel::new(1), Performance::new()).to_bytes();
+        data[15] = b'X';
+        assert_eq!(
+            Message::parse(&data).unwrap_err(),
+            ParseError::InvalidData(6, "classification: Unknown classification name".to_string()));
+    }
+
+    #[test]
+    fn test_message_bad_checksum() {
+        let mut data = make_rom1a_message();
+        let index = data.len() - 2;
+        data[index] = 0x00;
+        assert_eq!(
+            Message::parse(&data).unwrap_err(),
+            ParseError::InvalidChecksum(0x33, 0x00));
+    }
+
+    #[test]
+    fn test_header_bad_format() {
+        assert_eq!(
+            Header::parse(&[0x00, 0x02, 0x01, 0x1b]).unwrap_err(),
+            ParseError::InvalidData(1, "format: Bad format value".to_string()));
+    }
+
+    #[test]
+    fn test_header_short_data() {
+        assert!(Header::parse(&[0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_scan_mixed_data() {
+        let voice_message = Message::voice(MidiChannel::new(1), Voice::new()).to_bytes();
+        let roland_message = vec![0xF0, 0x41, 0x10, 0x16, 0x12, 0x00, 0xF7];
+        let cartridge_message = make_rom1a_message();
+
+        let mut data = vec![0x00, 0x01];  // leading garbage
+        data.extend(&voice_message);
+        data.extend(&roland_message);
+        data.extend(&cartridge_message);
+        data.extend(&voice_message[..20]);  // truncated at the end
+
+        let entries = scan(&data);
+        assert_eq!(entries.len(), 5);
+
+        assert!(matches!(entries[0],
+            Entry::Skipped { offset: 0, length: 2, reason: SkipReason::Garbage }));
+        assert!(matches!(entries[1], Entry::Message { offset: 2, .. }));
+        match &entries[2] {
+            Entry::Skipped { reason, .. } => assert_eq!(
+                *reason, SkipReason::OtherManufacturer(Manufacturer::Standard(0x41))),
+            _ => panic!("expected a skipped message"),
+        }
+        match &entries[3] {
+            Entry::Message { message, .. } => assert_eq!(message.header.format, Format::Cartridge),
+            _ => panic!("expected a cartridge"),
+        }
+        assert!(matches!(entries[4],
+            Entry::Skipped { length: 20, reason: SkipReason::Unterminated, .. }));
+    }
+
+    #[test]
+    fn test_scan_bad_checksum() {
+        let mut data = make_rom1a_message();
+        let index = data.len() - 2;
+        data[index] = 0x00;
+        let entries = scan(&data);
+        assert!(matches!(&entries[0],
+            Entry::Skipped { reason: SkipReason::Invalid(ParseError::InvalidChecksum(_, _)), .. }));
+    }
 }
@@ -0,0 +1,444 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use syxpack::{
+    ParseError,
+    Ranged,
+};
+
+use crate::dx7::{
+    nested_error,
+    Algorithm,
+    Coarse,
+    Depth,
+    Detune,
+    Level,
+    Sensitivity,
+    Transpose,
+};
+use crate::dx7::cartridge::{
+    Cartridge,
+    VOICE_COUNT,
+};
+use crate::dx7::envelope::{
+    Envelope,
+    Rate,
+};
+use crate::dx7::lfo::{
+    Lfo,
+    LfoWaveform,
+};
+use crate::dx7::operator::{
+    Key,
+    KeyboardLevelScaling,
+    Operator,
+    OperatorMode,
+    Scaling,
+    ScalingCurve,
+};
+use crate::dx7::voice::{
+    Voice,
+    VoiceName,
+    OPERATOR_COUNT,
+    VOICE_NAME_LENGTH,
+};
+
+static WAVEFORMS: [LfoWaveform; 6] = [
+    LfoWaveform::Triangle,
+    LfoWaveform::SawDown,
+    LfoWaveform::SawUp,
+    LfoWaveform::Square,
+    LfoWaveform::Sine,
+    LfoWaveform::SampleAndHold,
+];
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn list<T: Ranged>(values: &[T]) -> String {
+    let items: Vec<String> = values.iter().map(|v| v.value().to_string()).collect();
+    format!("[{}]", items.join(", "))
+}
+
+impl Voice {
+    /// Gets the voice in a plain-text format that can be edited by hand
+    /// and parsed back with `from_text`. The format is a small subset
+    /// of TOML: `key = value` lines in `[lfo]`, `[pitch_envelope]` and
+    /// `[op1]`...`[op6]` sections, with numbers, `true`/`false`,
+    /// quoted strings and lists of four numbers. Comments start with `#`.
+    ///
+    /// ```text
+    /// name = "BRASS   1"
+    /// algorithm = 22
+    ///
+    /// [op1]
+    /// mode = "ratio"
+    /// envelope_rates = [72, 76, 99, 71]
+    /// breakpoint = 39  # C3
+    /// left_curve = "lin-"
+    /// ```
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        // Writing to a string never fails.
+        let _ = self.write_text(&mut text);
+        text
+    }
+
+    fn write_text(&self, text: &mut String) -> std::fmt::Result {
+        writeln!(text, "name = {}", quote(&self.name.value()))?;
+        writeln!(text, "algorithm = {}", self.alg.value())?;
+        writeln!(text, "feedback = {}", self.feedback.value())?;
+        writeln!(text, "oscillator_sync = {}", self.osc_sync)?;
+        writeln!(text, "transpose = {}", self.transpose.value())?;
+        writeln!(text, "pitch_mod_sensitivity = {}", self.pitch_mod_sens.value())?;
+
+        writeln!(text, "\n[lfo]")?;
+        writeln!(text, "speed = {}", self.lfo.speed.value())?;
+        writeln!(text, "delay = {}", self.lfo.delay.value())?;
+        writeln!(text, "pitch_mod_depth = {}", self.lfo.pmd.value())?;
+        writeln!(text, "amp_mod_depth = {}", self.lfo.amd.value())?;
+        writeln!(text, "sync = {}", self.lfo.sync)?;
+        writeln!(text, "waveform = {}", quote(&self.lfo.waveform.to_string()))?;
+
+        writeln!(text, "\n[pitch_envelope]")?;
+        writeln!(text, "rates = {}", list(&self.peg.rates))?;
+        writeln!(text, "levels = {}", list(&self.peg.levels))?;
+
+        for (i, op) in self.operators.iter().enumerate() {
+            let scaling = &op.kbd_level_scaling;
+            writeln!(text, "\n[op{}]", i + 1)?;
+            writeln!(text, "mode = {}", quote(&op.mode.to_string()))?;
+            writeln!(text, "coarse = {}", op.coarse.value())?;
+            writeln!(text, "fine = {}", op.fine.value())?;
+            writeln!(text, "detune = {}", op.detune.value())?;
+            writeln!(text, "output_level = {}", op.output_level.value())?;
+            writeln!(text, "envelope_rates = {}", list(&op.eg.rates))?;
+            writeln!(text, "envelope_levels = {}", list(&op.eg.levels))?;
+            writeln!(text, "rate_scaling = {}", op.kbd_rate_scaling.value())?;
+            writeln!(text, "breakpoint = {}  # {}", scaling.breakpoint.value(), scaling.breakpoint.name())?;
+            writeln!(text, "left_depth = {}", scaling.left.depth.value())?;
+            writeln!(text, "left_curve = {}", quote(scaling.left.curve.name()))?;
+            writeln!(text, "right_depth = {}", scaling.right.depth.value())?;
+            writeln!(text, "right_curve = {}", quote(scaling.right.curve.name()))?;
+            writeln!(text, "velocity_sensitivity = {}", op.key_vel_sens.value())?;
+            writeln!(text, "amp_mod_sensitivity = {}", op.amp_mod_sens.value())?;
+        }
+        Ok(())
+    }
+
+    /// Parses a voice from the text format written by `to_text`.
+    /// Every setting must be present exactly once, and values out
+    /// of range are errors. The error offset is the line number.
+    pub fn from_text(text: &str) -> Result<Voice, ParseError> {
+        let document = Document::parse(text)?;
+
+        let mut operators = [Operator::new(); OPERATOR_COUNT];
+        for (i, op) in operators.iter_mut().enumerate() {
+            let section = format!("op{}", i + 1);
+            let s = section.as_str();
+            *op = Operator {
+                eg: Envelope::new_rate_level(
+                    document.list::<Rate>(s, "envelope_rates")?,
+                    document.list::<Level>(s, "envelope_levels")?),
+                kbd_level_scaling: KeyboardLevelScaling {
+                    breakpoint: document.ranged::<Key>(s, "breakpoint")?,
+                    left: Scaling {
+                        depth: document.ranged::<Level>(s, "left_depth")?,
+                        curve: document.choice(s, "left_curve", parse_curve)?,
+                    },
+                    right: Scaling {
+                        depth: document.ranged::<Level>(s, "right_depth")?,
+                        curve: document.choice(s, "right_curve", parse_curve)?,
+                    },
+                },
+                kbd_rate_scaling: document.ranged::<Depth>(s, "rate_scaling")?,
+                amp_mod_sens: document.ranged::<Sensitivity>(s, "amp_mod_sensitivity")?,
+                key_vel_sens: document.ranged::<Depth>(s, "velocity_sensitivity")?,
+                output_level: document.ranged::<Level>(s, "output_level")?,
+                mode: document.choice(s, "mode", parse_mode)?,
+                coarse: document.ranged::<Coarse>(s, "coarse")?,
+                fine: document.ranged::<Level>(s, "fine")?,
+                detune: document.ranged::<Detune>(s, "detune")?,
+            };
+        }
+
+        let name = document.string("", "name")?;
+        if !VoiceName::is_valid(&name) {
+            let (line, _) = document.get("", "name")?;
+            return Err(ParseError::InvalidData(line as u32,
+                format!("name: expected at most {} ASCII characters", VOICE_NAME_LENGTH)));
+        }
+
+        let voice = Voice {
+            operators,
+            peg: Envelope::new_rate_level(
+                document.list::<Rate>("pitch_envelope", "rates")?,
+                document.list::<Level>("pitch_envelope", "levels")?),
+            alg: document.ranged::<Algorithm>("", "algorithm")?,
+            feedback: document.ranged::<Depth>("", "feedback")?,
+            osc_sync: document.boolean("", "oscillator_sync")?,
+            lfo: Lfo {
+                speed: document.ranged::<Level>("lfo", "speed")?,
+                delay: document.ranged::<Level>("lfo", "delay")?,
+                pmd: document.ranged::<Level>("lfo", "pitch_mod_depth")?,
+                amd: document.ranged::<Level>("lfo", "amp_mod_depth")?,
+                sync: document.boolean("lfo", "sync")?,
+                waveform: document.choice("lfo", "waveform", parse_waveform)?,
+            },
+            pitch_mod_sens: document.ranged::<Depth>("", "pitch_mod_sensitivity")?,
+            transpose: document.ranged::<Transpose>("", "transpose")?,
+            name: VoiceName::new(&name),
+        };
+
+        document.check_unused()?;
+        Ok(voice)
+    }
+}
+
+/// Line that separates the voices in the text form of a cartridge.
+pub const VOICE_SEPARATOR: &str = "---";
+
+impl Cartridge {
+    /// Gets the cartridge in the text format of `Voice::to_text`,
+    /// with the voices separated by `VOICE_SEPARATOR` lines.
+    pub fn to_text(&self) -> String {
+        let voices: Vec<String> = self.voices.iter().map(|voice| voice.to_text()).collect();
+        voices.join(&format!("{}\n", VOICE_SEPARATOR))
+    }
+
+    /// Parses a cartridge from the text format written by `to_text`.
+    /// The error offset is the line number.
+    pub fn from_text(text: &str) -> Result<Cartridge, ParseError> {
+        let mut documents = vec![(0, String::new())];
+        for (index, line) in text.lines().enumerate() {
+            if line.trim() == VOICE_SEPARATOR {
+                documents.push((index + 1, String::new()));
+            } else if let Some((_, document)) = documents.last_mut() {
+                document.push_str(line);
+                document.push('\n');
+            }
+        }
+        if documents.len() != VOICE_COUNT {
+            return Err(ParseError::InvalidData(0,
+                format!("expected {} voices, got {}", VOICE_COUNT, documents.len())));
+        }
+
+        let mut voices = Vec::new();
+        for (i, (base, document)) in documents.iter().enumerate() {
+            let voice = Voice::from_text(document)
+                .map_err(|e| nested_error(e, *base, &format!("voices[{}]", i)))?;
+            voices.push(voice);
+        }
+        Ok(Cartridge { voices })
+    }
+}
+
+fn parse_curve(value: &str) -> Option<ScalingCurve> {
+    value.parse().ok()
+}
+
+fn parse_mode(value: &str) -> Option<OperatorMode> {
+    [OperatorMode::Ratio, OperatorMode::Fixed].into_iter().find(|mode| mode.to_string() == value)
+}
+
+fn parse_waveform(value: &str) -> Option<LfoWaveform> {
+    WAVEFORMS.iter().copied().find(|waveform| waveform.to_string() == value)
+}
+
+// Settings of a text document by section and key,
+// with the line numbers and raw values.
+struct Document {
+    values: HashMap<(String, String), (usize, String)>,
+    used: RefCell<Vec<(String, String)>>,
+}
+
+impl Document {
+    fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut values = HashMap::new();
+        let mut section = String::new();
+        for (index, line) in text.lines().enumerate() {
+            let number = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(ParseError::InvalidData(number as u32,
+                    format!("expected \"key = value\", got \"{}\"", line)));
+            };
+            let id = (section.clone(), key.trim().to_string());
+            if values.contains_key(&id) {
+                return Err(ParseError::InvalidData(number as u32,
+                    format!("{}: duplicate setting", display_key(&id.0, &id.1))));
+            }
+            values.insert(id, (number, value.trim().to_string()));
+        }
+        Ok(Self { values, used: Default::default() })
+    }
+
+    fn get(&self, section: &str, key: &str) -> Result<(usize, &str), ParseError> {
+        let id = (section.to_string(), key.to_string());
+        match self.values.get(&id) {
+            Some((line, value)) => {
+                self.used.borrow_mut().push(id);
+                Ok((*line, value))
+            },
+            None => Err(ParseError::InvalidData(0,
+                format!("{}: missing setting", display_key(section, key)))),
+        }
+    }
+
+    fn error(&self, line: usize, section: &str, key: &str, reason: &str) -> ParseError {
+        ParseError::InvalidData(line as u32, format!("{}: {}", display_key(section, key), reason))
+    }
+
+    fn number<T: Ranged>(&self, line: usize, section: &str, key: &str, value: &str) -> Result<T, ParseError> {
+        match value.trim().parse::<i32>() {
+            Ok(n) if T::contains(n) => Ok(T::new(n)),
+            Ok(n) => Err(self.error(line, section, key,
+                &format!("value {} out of range {}...{}", n, T::FIRST, T::LAST))),
+            Err(_) => Err(self.error(line, section, key, &format!("expected a number, got {}", value))),
+        }
+    }
+
+    fn ranged<T: Ranged>(&self, section: &str, key: &str) -> Result<T, ParseError> {
+        let (line, value) = self.get(section, key)?;
+        self.number(line, section, key, value)
+    }
+
+    fn list<T: Ranged + Copy>(&self, section: &str, key: &str) -> Result<[T; 4], ParseError> {
+        let (line, value) = self.get(section, key)?;
+        let items: Vec<&str> = value.strip_prefix('[').and_then(|v| v.strip_suffix(']'))
+            .map(|v| v.split(',').collect())
+            .unwrap_or_default();
+        if items.len() != 4 {
+            return Err(self.error(line, section, key, "expected a list of four numbers"));
+        }
+        let mut result = [T::new(T::DEFAULT); 4];
+        for (item, text) in result.iter_mut().zip(items) {
+            *item = self.number(line, section, key, text)?;
+        }
+        Ok(result)
+    }
+
+    fn boolean(&self, section: &str, key: &str) -> Result<bool, ParseError> {
+        match self.get(section, key)? {
+            (_, "true") => Ok(true),
+            (_, "false") => Ok(false),
+            (line, _) => Err(self.error(line, section, key, "expected true or false")),
+        }
+    }
+
+    fn string(&self, section: &str, key: &str) -> Result<String, ParseError> {
+        let (line, value) = self.get(section, key)?;
+        unquote(value).ok_or_else(|| self.error(line, section, key, "expected a quoted string"))
+    }
+
+    fn choice<T>(&self, section: &str, key: &str, parse: fn(&str) -> Option<T>) -> Result<T, ParseError> {
+        let value = self.string(section, key)?;
+        let (line, _) = self.get(section, key)?;
+        parse(&value).ok_or_else(|| self.error(line, section, key, &format!("unknown value \"{}\"", value)))
+    }
+
+    // Checks that there are no settings left over, to catch typos.
+    fn check_unused(&self) -> Result<(), ParseError> {
+        let used = self.used.borrow();
+        let unused = self.values.iter()
+            .filter(|(id, _)| !used.contains(id))
+            .min_by_key(|(_, (line, _))| *line);
+        match unused {
+            Some(((section, key), (line, _))) => Err(self.error(*line, section, key, "unknown setting")),
+            None => Ok(()),
+        }
+    }
+}
+
+fn display_key(section: &str, key: &str) -> String {
+    if section.is_empty() { key.to_string() } else { format!("{}.{}", section, key) }
+}
+
+// Removes a comment from the end of the line, but not from inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {},
+        }
+    }
+    line
+}
+
+fn unquote(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+    let mut result = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.push(chars.next()?),
+            '"' => return None,
+            _ => result.push(c),
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dx7::cartridge::Cartridge;
+    use crate::dx7::rom1a;
+    use syxpack::SystemExclusiveData;
+
+    #[test]
+    fn test_round_trip() {
+        let cartridge = rom1a();
+        for voice in &cartridge.voices {
+            let text = voice.to_text();
+            let result = Voice::from_text(&text).unwrap();
+            assert_eq!(result.to_bytes(), voice.to_bytes(), "{}", voice.name);
+        }
+
+        let voice = Voice { name: VoiceName::new("A \"#\\ B"), ..Voice::new() };
+        assert_eq!(Voice::from_text(&voice.to_text()).unwrap().name.value(), "A \"#\\ B");
+    }
+
+    #[test]
+    fn test_cartridge_round_trip() {
+        let cartridge = rom1a();
+        let text = cartridge.to_text();
+        assert_eq!(Cartridge::from_text(&text).unwrap().to_bytes(), cartridge.to_bytes());
+
+        let error = Cartridge::from_text(&text.replacen("algorithm = 18", "algorithm = 0", 1)).unwrap_err();
+        assert!(matches!(error, ParseError::InvalidData(_, message) if message.starts_with("voices[")));
+        assert!(Cartridge::from_text(&Voice::new().to_text()).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let text = Voice::new().to_text();
+        let edited = text.replace("algorithm = 1", "algorithm = 33");
+        assert_eq!(Voice::from_text(&edited).unwrap_err(),
+            ParseError::InvalidData(2, "algorithm: value 33 out of range 1...32".to_string()));
+
+        let edited = text.replace("[op3]\nmode = \"ratio\"", "[op3]\nmode = \"free\"");
+        assert!(Voice::from_text(&edited).is_err());
+
+        let edited = text.replace("feedback", "fedback");
+        assert_eq!(Voice::from_text(&edited).unwrap_err(),
+            ParseError::InvalidData(0, "feedback: missing setting".to_string()));
+
+        let edited = format!("{}\nfoo = 1\n", text);
+        assert!(matches!(Voice::from_text(&edited), Err(ParseError::InvalidData(_, message)) if message == "op6.foo: unknown setting"));
+    }
+}
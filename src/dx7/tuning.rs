@@ -0,0 +1,510 @@
+use std::fmt;
+
+use rand::Rng;
+use syxpack::{
+    ParseError,
+    Ranged,
+    ranged_impl,
+    Encoding,
+};
+
+use crate::dx7::{
+    Note,
+    check_length,
+    invalid_field,
+};
+use crate::dx7::policy::ParseContext;
+use crate::dx7::voice::Voice;
+
+/// Number of fine tuning steps in a semitone.
+pub const STEPS_PER_SEMITONE: i32 = 85;
+
+/// Number of notes in an octave tuning.
+pub const OCTAVE_NOTE_COUNT: usize = 12;
+
+/// Number of notes in a full keyboard tuning.
+pub const KEYBOARD_NOTE_COUNT: usize = 128;
+
+/// Size of the octave tuning data (two bytes per note).
+pub const OCTAVE_TUNING_SIZE: usize = OCTAVE_NOTE_COUNT * 2;
+
+/// Size of the full keyboard tuning data (two bytes per note).
+pub const KEYBOARD_TUNING_SIZE: usize = KEYBOARD_NOTE_COUNT * 2;
+
+/// Frequency of MIDI note 0 in equal temperament with A4 = 440 Hz.
+const NOTE_ZERO_FREQUENCY: f64 = 8.175798915643707;
+
+/// Fine tuning (0...84) in steps of 1/85 semitone.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Fine(i32);
+
+ranged_impl!(Fine, 0, 84, 0);
+
+impl Encoding for Fine { }  // identity mapping, no adjustment needed
+
+/// Pitch of a tuned note: a MIDI note number and a fine offset upwards.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Pitch {
+    pub note: Note,
+    pub fine: Fine,
+}
+
+impl Pitch {
+    /// Makes a new pitch from a note and fine offset.
+    pub fn new(note: Note, fine: Fine) -> Self {
+        Self { note, fine }
+    }
+
+    /// Gets the pitch in fine tuning steps above note 0.
+    pub fn steps(&self) -> i32 {
+        self.note.value() * STEPS_PER_SEMITONE + self.fine.value()
+    }
+
+    /// Makes a pitch from fine tuning steps above note 0,
+    /// clamped to the range of notes.
+    pub fn from_steps(steps: i32) -> Self {
+        let last = Note::LAST * STEPS_PER_SEMITONE + Fine::LAST;
+        let steps = num::clamp(steps, 0, last);
+        Self {
+            note: Note::new(steps / STEPS_PER_SEMITONE),
+            fine: Fine::new(steps % STEPS_PER_SEMITONE),
+        }
+    }
+
+    /// Gets the pitch in cents above note 0.
+    pub fn cents(&self) -> f64 {
+        self.steps() as f64 * 100.0 / STEPS_PER_SEMITONE as f64
+    }
+
+    /// Makes a pitch from cents above note 0, rounded to the nearest step.
+    pub fn from_cents(cents: f64) -> Self {
+        Self::from_steps((cents * STEPS_PER_SEMITONE as f64 / 100.0).round() as i32)
+    }
+
+    /// Gets the frequency of the pitch in hertz.
+    pub fn frequency(&self) -> f64 {
+        NOTE_ZERO_FREQUENCY * 2.0_f64.powf(self.cents() / 1200.0)
+    }
+
+    /// Makes a pitch from a frequency in hertz.
+    pub fn from_frequency(frequency: f64) -> Self {
+        Self::from_cents(1200.0 * (frequency / NOTE_ZERO_FREQUENCY).log2())
+    }
+}
+
+impl fmt::Display for Pitch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} +{}/85", self.note.name(), self.fine)
+    }
+}
+
+/// DX7II micro tuning. An octave tuning gives the pitches of the notes
+/// C...B in the lowest octave (notes 0...11), and repeats them an octave
+/// higher for every octave. A full keyboard tuning gives the pitch
+/// of each of the 128 notes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Tuning {
+    Octave([Pitch; OCTAVE_NOTE_COUNT]),
+    Keyboard(Box<[Pitch; KEYBOARD_NOTE_COUNT]>),
+}
+
+impl Tuning {
+    /// Makes an equal tempered octave tuning.
+    pub fn equal_temperament() -> Self {
+        let mut pitches = [Pitch::new(Note::new(0), Fine::new(0)); OCTAVE_NOTE_COUNT];
+        for (i, pitch) in pitches.iter_mut().enumerate() {
+            pitch.note = Note::new(i as i32);
+        }
+        Tuning::Octave(pitches)
+    }
+
+    /// Makes a full keyboard tuning with the pitches of notes 0...127.
+    pub fn keyboard(pitches: [Pitch; KEYBOARD_NOTE_COUNT]) -> Self {
+        Tuning::Keyboard(Box::new(pitches))
+    }
+
+    /// Gets the pitches of the tuning, 12 for an octave tuning
+    /// and 128 for a full keyboard tuning.
+    pub fn pitches(&self) -> &[Pitch] {
+        match self {
+            Tuning::Octave(pitches) => pitches,
+            Tuning::Keyboard(pitches) => pitches.as_slice(),
+        }
+    }
+
+    /// Gets the size of the tuning data, which depends on the kind of tuning.
+    pub fn data_size(&self) -> usize {
+        match self {
+            Tuning::Octave(_) => OCTAVE_TUNING_SIZE,
+            Tuning::Keyboard(_) => KEYBOARD_TUNING_SIZE,
+        }
+    }
+
+    /// Gets the pitch of `note` in this tuning.
+    pub fn pitch(&self, note: Note) -> Pitch {
+        match self {
+            Tuning::Octave(pitches) => {
+                let n = note.value() as usize;
+                let octave = (n / OCTAVE_NOTE_COUNT) as i32;
+                let base = pitches[n % OCTAVE_NOTE_COUNT];
+                Pitch::from_steps(base.steps() + octave * 12 * STEPS_PER_SEMITONE)
+            },
+            Tuning::Keyboard(pitches) => pitches[note.value() as usize],
+        }
+    }
+
+    /// Gets the frequency of `note` in this tuning, in hertz.
+    pub fn frequency(&self, note: Note) -> f64 {
+        self.pitch(note).frequency()
+    }
+
+    /// Makes a tuning from SysEx bytes, handling out-of-range values
+    /// according to the context policy. The size of the data
+    /// determines the kind of tuning.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        let count = if data.len() == OCTAVE_TUNING_SIZE {
+            OCTAVE_NOTE_COUNT
+        } else {
+            check_length(data, KEYBOARD_TUNING_SIZE, "tuning")?;
+            KEYBOARD_NOTE_COUNT
+        };
+
+        let mut pitches = Vec::<Pitch>::new();
+        for i in 0..count {
+            pitches.push(Pitch {
+                note: context.ranged::<Note>(data, i * 2, &format!("notes[{}].note", i))?,
+                fine: context.clamped::<Fine>(data, i * 2 + 1, &format!("notes[{}].fine", i))?,
+            });
+        }
+
+        if count == OCTAVE_NOTE_COUNT {
+            let mut octave = [pitches[0]; OCTAVE_NOTE_COUNT];
+            octave.copy_from_slice(&pitches);
+            Ok(Tuning::Octave(octave))
+        } else {
+            let mut keyboard = [pitches[0]; KEYBOARD_NOTE_COUNT];
+            keyboard.copy_from_slice(&pitches);
+            Ok(Tuning::keyboard(keyboard))
+        }
+    }
+
+    /// Makes a full keyboard tuning from a Scala scale (`.scl`) and an
+    /// optional keyboard mapping (`.kbm`). Without a mapping, middle C
+    /// (note 60) is the first degree of the scale, and A (note 69) is 440 Hz.
+    /// Errors report the line number in place of the offset.
+    pub fn from_scala(scl: &str, kbm: Option<&str>) -> Result<Self, ParseError> {
+        let degrees = parse_scale(scl)?;
+        let mapping = match kbm {
+            Some(text) => parse_mapping(text)?,
+            None => Mapping::default(),
+        };
+
+        // Degree 0 is the tonic, the last degree of the scale is the period.
+        let cents_of = |degree: i32| -> f64 {
+            let n = degrees.len() as i32;
+            let step = degree.rem_euclid(n);
+            let base = if step == 0 { 0.0 } else { degrees[step as usize - 1] };
+            degree.div_euclid(n) as f64 * degrees[degrees.len() - 1] + base
+        };
+
+        let reference_degree = mapping.degree(mapping.reference)
+            .unwrap_or(mapping.reference - mapping.middle);
+        let reference_cents = cents_of(reference_degree);
+
+        let mut pitches = [Pitch::new(Note::new(0), Fine::new(0)); KEYBOARD_NOTE_COUNT];
+        for (n, pitch) in (0..).zip(pitches.iter_mut()) {
+            let degree = if n < mapping.first || n > mapping.last { None } else { mapping.degree(n) };
+            *pitch = match degree {
+                Some(d) => {
+                    let frequency = mapping.frequency
+                        * 2.0_f64.powf((cents_of(d) - reference_cents) / 1200.0);
+                    Pitch::from_frequency(frequency)
+                },
+                None => Pitch::new(Note::new(n), Fine::new(0)),  // unmapped keys play in equal temperament
+            };
+        }
+
+        Ok(Tuning::keyboard(pitches))
+    }
+
+    /// Converts the tuning to a Scala scale and keyboard mapping,
+    /// with the first note of the tuning as the tonic.
+    pub fn to_scala(&self, description: &str) -> (String, String) {
+        let pitches = self.pitches();
+        let tonic = pitches[0];
+
+        let mut degrees: Vec<f64> = pitches[1..].iter().map(|p| p.cents() - tonic.cents()).collect();
+        let period = match self {
+            Tuning::Octave(_) => 1200.0,
+            Tuning::Keyboard(_) => degrees[degrees.len() - 1] + 100.0,  // never reached
+        };
+        degrees.push(period);
+
+        let mut scl = String::new();
+        scl.push_str("! Exported from DX7II micro tuning\n");
+        scl.push_str(&format!("{}\n", description));
+        scl.push_str(&format!("{}\n", degrees.len()));
+        for cents in degrees {
+            scl.push_str(&format!("{:.5}\n", cents));
+        }
+
+        let size = match self {
+            Tuning::Octave(_) => OCTAVE_NOTE_COUNT,
+            Tuning::Keyboard(_) => 0,  // linear mapping
+        };
+        let mut kbm = String::new();
+        kbm.push_str("! Exported from DX7II micro tuning\n");
+        kbm.push_str(&format!("{}\n0\n127\n0\n0\n{:.6}\n{}\n", size, tonic.frequency(), size));
+        for i in 0..size {
+            kbm.push_str(&format!("{}\n", i));
+        }
+
+        (scl, kbm)
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning::equal_temperament()
+    }
+}
+
+// Tuning does not implement `SystemExclusiveData`, because the size
+// of the data depends on the kind of tuning.
+impl Tuning {
+    /// Makes a tuning from SysEx bytes, clamping or replacing
+    /// out-of-range values.
+    pub fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
+
+    /// Gets the SysEx bytes of the tuning, `data_size()` bytes in all.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for pitch in self.pitches() {
+            data.push(pitch.note.encode());
+            data.push(pitch.fine.encode());
+        }
+        data
+    }
+}
+
+impl fmt::Display for Tuning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<String> = self.pitches().iter().map(|p| p.to_string()).collect();
+        write!(f, "{}", names.join("\n"))
+    }
+}
+
+impl Voice {
+    /// Gets the frequency of `note` played with this voice in `tuning`,
+    /// taking the voice transpose into account.
+    pub fn note_frequency(&self, note: Note, tuning: &Tuning) -> f64 {
+        let transposed = num::clamp(note.value() + self.transpose.value(), Note::FIRST, Note::LAST);
+        tuning.frequency(Note::new(transposed))
+    }
+}
+
+// Gets the non-comment lines of a Scala file with their line numbers.
+fn scala_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.starts_with('!'))
+}
+
+// Parses the scale degrees of a Scala scale in cents.
+// The last degree is the period of the scale.
+fn parse_scale(text: &str) -> Result<Vec<f64>, ParseError> {
+    let mut lines = scala_lines(text).skip(1);  // description
+    let (line_number, count_line) = lines.next()
+        .ok_or_else(|| invalid_field(0, "scl", "missing note count"))?;
+    let count: usize = count_line.split_whitespace().next().unwrap_or("").parse()
+        .map_err(|_| invalid_field(line_number, "scl", "bad note count"))?;
+    if count == 0 {
+        return Err(invalid_field(line_number, "scl", "scale has no notes"));
+    }
+
+    let mut degrees = Vec::<f64>::new();
+    for (line_number, line) in lines.filter(|(_, line)| !line.is_empty()).take(count) {
+        let value = line.split_whitespace().next().unwrap_or("");
+        let cents = if value.contains('.') {
+            value.parse::<f64>().ok()
+        } else {
+            let mut parts = value.splitn(2, '/');
+            let numerator = parts.next().and_then(|n| n.parse::<f64>().ok());
+            let denominator = match parts.next() {
+                Some(d) => d.parse::<f64>().ok(),
+                None => Some(1.0),
+            };
+            match (numerator, denominator) {
+                (Some(n), Some(d)) if n > 0.0 && d > 0.0 => Some(1200.0 * (n / d).log2()),
+                _ => None,
+            }
+        };
+        degrees.push(cents.ok_or_else(|| invalid_field(line_number, "scl", &format!("bad pitch '{}'", value)))?);
+    }
+
+    if degrees.len() != count {
+        return Err(invalid_field(0, "scl", &format!("expected {} notes, found {}", count, degrees.len())));
+    }
+    Ok(degrees)
+}
+
+// Scala keyboard mapping.
+struct Mapping {
+    size: i32,
+    first: i32,
+    last: i32,
+    middle: i32,
+    reference: i32,
+    frequency: f64,
+    octave_degree: i32,
+    keys: Vec<Option<i32>>,  // None for unmapped keys
+}
+
+impl Mapping {
+    // Gets the scale degree of `note`, or `None` if the key is unmapped.
+    fn degree(&self, note: i32) -> Option<i32> {
+        let offset = note - self.middle;
+        if self.size == 0 {
+            return Some(offset);
+        }
+        let octave = offset.div_euclid(self.size);
+        let index = offset.rem_euclid(self.size) as usize;
+        self.keys.get(index).copied().flatten().map(|d| d + octave * self.octave_degree)
+    }
+}
+
+impl Default for Mapping {
+    fn default() -> Self {
+        Mapping {
+            size: 0,
+            first: 0,
+            last: 127,
+            middle: 60,
+            reference: 69,
+            frequency: 440.0,
+            octave_degree: 0,
+            keys: Vec::new(),
+        }
+    }
+}
+
+fn parse_mapping(text: &str) -> Result<Mapping, ParseError> {
+    let mut values = scala_lines(text)
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(n, line)| (n, line.split_whitespace().next().unwrap_or("")));
+    let names = ["map size", "first note", "last note", "middle note", "reference note"];
+    let mut header = Vec::<i32>::new();
+    for name in names {
+        let (line_number, value) = values.next()
+            .ok_or_else(|| invalid_field(0, "kbm", &format!("missing {}", name)))?;
+        header.push(value.parse()
+            .map_err(|_| invalid_field(line_number, "kbm", &format!("bad {}", name)))?);
+    }
+    let (line_number, value) = values.next()
+        .ok_or_else(|| invalid_field(0, "kbm", "missing reference frequency"))?;
+    let frequency: f64 = value.parse()
+        .map_err(|_| invalid_field(line_number, "kbm", "bad reference frequency"))?;
+    let (line_number, value) = values.next()
+        .ok_or_else(|| invalid_field(0, "kbm", "missing octave degree"))?;
+    let octave_degree: i32 = value.parse()
+        .map_err(|_| invalid_field(line_number, "kbm", "bad octave degree"))?;
+
+    // Keys missing from the end of the mapping are unmapped.
+    let mut keys = Vec::<Option<i32>>::new();
+    for (line_number, value) in values.take(header[0].max(0) as usize) {
+        keys.push(if value == "x" {
+            None
+        } else {
+            Some(value.parse().map_err(|_| invalid_field(line_number, "kbm", &format!("bad key '{}'", value)))?)
+        });
+    }
+
+    Ok(Mapping {
+        size: header[0],
+        first: header[1],
+        last: header[2],
+        middle: header[3],
+        reference: header[4],
+        frequency,
+        octave_degree,
+        keys,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_equal_temperament() {
+        let tuning = Tuning::equal_temperament();
+        assert!((tuning.frequency(Note::new(69)) - 440.0).abs() < 0.01);
+        assert_eq!(tuning.pitch(Note::new(60)).to_string(), "C3 +0/85");
+        assert_eq!(tuning.to_bytes().len(), OCTAVE_TUNING_SIZE);
+        assert_eq!(tuning.data_size(), OCTAVE_TUNING_SIZE);
+    }
+
+    #[test]
+    fn test_sysex_round_trip() {
+        let mut pitches = [Pitch::new(Note::new(0), Fine::new(0)); KEYBOARD_NOTE_COUNT];
+        for (i, pitch) in pitches.iter_mut().enumerate() {
+            *pitch = Pitch::from_steps(i as i32 * 80);
+        }
+        let tuning = Tuning::keyboard(pitches);
+        let data = tuning.to_bytes();
+        assert_eq!(data.len(), KEYBOARD_TUNING_SIZE);
+        assert_eq!(tuning.data_size(), KEYBOARD_TUNING_SIZE);
+        assert_eq!(Tuning::parse(&data).unwrap(), tuning);
+
+        let (scl, _) = tuning.to_scala("Keyboard");
+        assert_eq!(scl.lines().nth(2), Some("128"));
+    }
+
+    #[test]
+    fn test_scala_just_intonation() {
+        let scl = "! just.scl
+Just major
+ 7
+ 9/8
+ 5/4
+ 4/3
+ 3/2
+ 5/3
+ 15/8
+ 2/1
+";
+        let tuning = Tuning::from_scala(scl, None).unwrap();
+        let a = tuning.frequency(Note::new(69));
+        assert!((a - 440.0).abs() < 0.5, "A = {}", a);
+        // Without a mapping, consecutive keys play consecutive degrees,
+        // so note 69 is the third degree an octave above middle C.
+        let c = tuning.frequency(Note::new(60));
+        assert!((c - 176.0).abs() < 0.5, "C = {}", c);
+        let g = tuning.frequency(Note::new(64));
+        assert!((g - 264.0).abs() < 0.5, "G = {}", g);
+    }
+
+    #[test]
+    fn test_scala_round_trip() {
+        let mut tuning = Tuning::equal_temperament();
+        if let Tuning::Octave(ref mut pitches) = tuning {
+            pitches[4].fine = Fine::new(50);
+        }
+        let (scl, kbm) = tuning.to_scala("Test");
+        let imported = Tuning::from_scala(&scl, Some(&kbm)).unwrap();
+        for n in 0..128 {
+            let note = Note::new(n);
+            assert_eq!(imported.pitch(note), tuning.pitch(note), "note {}", n);
+        }
+    }
+
+    #[test]
+    fn test_scala_bad_pitch() {
+        assert_eq!(
+            Tuning::from_scala("Bad\n1\nabc\n", None).unwrap_err(),
+            ParseError::InvalidData(3, "scl: bad pitch 'abc'".to_string()));
+    }
+}
@@ -0,0 +1,439 @@
+use std::fmt;
+
+use syxpack::{
+    ParseError,
+    Ranged,
+    Encoding,
+    MidiChannel,
+    SystemExclusiveData,
+};
+
+use crate::dx7::{
+    Detune,
+    Level,
+    Note,
+    Transpose,
+    check_length,
+    name_bytes,
+};
+use crate::dx7::cartridge::Cartridge;
+use crate::dx7::performance::VoiceNumber;
+use crate::dx7::policy::ParseContext;
+use crate::dx7::sysex::{
+    Message,
+    Payload,
+};
+use crate::dx7::voice::Voice;
+
+/// Number of tone generators in the TX802.
+pub const TONE_GENERATOR_COUNT: usize = 8;
+
+/// Number of per-TG parameters in the performance data.
+const TONE_GENERATOR_PARAMETER_COUNT: usize = 9;
+
+/// Length of the performance name.
+pub const PERFORMANCE_NAME_LENGTH: usize = 20;
+
+/// Size of the TX802 performance data (PCED).
+pub const PERFORMANCE_SIZE: usize =
+    TONE_GENERATOR_PARAMETER_COUNT * TONE_GENERATOR_COUNT + PERFORMANCE_NAME_LENGTH;
+
+/// Number of performances in a PMEM bank.
+pub const PERFORMANCE_COUNT: usize = 32;
+
+/// Size of the PMEM bank data.
+pub const PERFORMANCE_BANK_SIZE: usize = PERFORMANCE_COUNT * PERFORMANCE_SIZE;
+
+/// MIDI receive channel of a tone generator.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReceiveChannel {
+    Channel(MidiChannel),
+    Omni,
+}
+
+impl TryFrom<u8> for ReceiveChannel {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0..=15 => Ok(ReceiveChannel::Channel(MidiChannel::new(value as i32 + 1))),
+            16 => Ok(ReceiveChannel::Omni),
+            _ => Err("Bad receive channel value, expected 0...16")
+        }
+    }
+}
+
+impl From<ReceiveChannel> for u8 {
+    fn from(channel: ReceiveChannel) -> u8 {
+        match channel {
+            ReceiveChannel::Channel(channel) => channel.encode(),
+            ReceiveChannel::Omni => 16,
+        }
+    }
+}
+
+impl fmt::Display for ReceiveChannel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReceiveChannel::Channel(channel) => write!(f, "{}", channel),
+            ReceiveChannel::Omni => write!(f, "omni"),
+        }
+    }
+}
+
+/// Output assignment of a tone generator.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum OutputAssign {
+    Off,
+    One,
+    Two,
+    Both,
+}
+
+impl TryFrom<u8> for OutputAssign {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(OutputAssign::Off),
+            1 => Ok(OutputAssign::One),
+            2 => Ok(OutputAssign::Two),
+            3 => Ok(OutputAssign::Both),
+            _ => Err("Bad output assign value, expected 0...3")
+        }
+    }
+}
+
+impl From<OutputAssign> for u8 {
+    fn from(assign: OutputAssign) -> u8 {
+        assign as u8
+    }
+}
+
+impl fmt::Display for OutputAssign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            OutputAssign::Off => "off",
+            OutputAssign::One => "I",
+            OutputAssign::Two => "II",
+            OutputAssign::Both => "I+II",
+        })
+    }
+}
+
+/// Settings of one tone generator.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ToneGenerator {
+    pub channel: ReceiveChannel,
+    pub voice: VoiceNumber,  // 1 ~ 128
+    pub detune: Detune,  // -7 ~ +7
+    pub output_level: Level,  // 0 ~ 99
+    pub output_assign: OutputAssign,
+    pub note_limit_low: Note,  // 0 ~ 127
+    pub note_limit_high: Note,  // 0 ~ 127
+    pub note_shift: Transpose,  // -24 ~ +24
+    pub key_assign_group: bool,
+}
+
+impl ToneGenerator {
+    /// Makes new tone generator settings with the TX802 defaults.
+    pub fn new() -> Self {
+        Self {
+            channel: ReceiveChannel::Channel(MidiChannel::new(1)),
+            voice: VoiceNumber::new(1),
+            detune: Detune::default(),
+            output_level: Level::new(90),
+            output_assign: OutputAssign::Both,
+            note_limit_low: Note::new(0),
+            note_limit_high: Note::new(127),
+            note_shift: Transpose::default(),
+            key_assign_group: false,
+        }
+    }
+
+    /// Gets the voice of this tone generator from `cartridge`.
+    /// Voice number N refers to index N - 1 in the cartridge;
+    /// returns `None` if the cartridge has no such voice.
+    pub fn voice_in<'a>(&self, cartridge: &'a Cartridge) -> Option<&'a Voice> {
+        cartridge.voices.get(self.voice.value() as usize - 1)
+    }
+
+    /// Returns `true` if `note` is within the note limits.
+    pub fn plays(&self, note: Note) -> bool {
+        note.value() >= self.note_limit_low.value() && note.value() <= self.note_limit_high.value()
+    }
+}
+
+impl Default for ToneGenerator {
+    fn default() -> Self {
+        ToneGenerator::new()
+    }
+}
+
+impl fmt::Display for ToneGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "voice = {}, channel = {}, notes = {}...{}, shift = {}, detune = {}, level = {}, output = {}, key assign group = {}",
+            self.voice, self.channel,
+            self.note_limit_low, self.note_limit_high, self.note_shift,
+            self.detune, self.output_level, self.output_assign,
+            self.key_assign_group)
+    }
+}
+
+/// TX802 multi-timbral performance, setting up the eight tone generators
+/// (TG) to each play one voice on its own MIDI channel and key range.
+/// In SysEx the data is arranged by parameter,
+/// with the value for each of the eight tone generators in turn.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Performance {
+    pub tone_generators: [ToneGenerator; TONE_GENERATOR_COUNT],
+    pub name: String,
+}
+
+impl Performance {
+    /// Makes a new performance with the TX802 defaults.
+    pub fn new() -> Self {
+        Self {
+            tone_generators: [ToneGenerator::new(); TONE_GENERATOR_COUNT],
+            name: "INIT PERFORMANCE".to_string(),
+        }
+    }
+
+    /// Makes a performance from SysEx bytes, handling out-of-range values
+    /// according to the context policy.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, PERFORMANCE_SIZE, "TX802 performance")?;
+
+        let mut tone_generators = [ToneGenerator::new(); TONE_GENERATOR_COUNT];
+        for (i, tg) in tone_generators.iter_mut().enumerate() {
+            // Every parameter is found at the same position in each block of eight.
+            let o = |parameter: usize| parameter * TONE_GENERATOR_COUNT + i;
+            let name = |parameter: &str| format!("tg{}.{}", i + 1, parameter);
+
+            *tg = ToneGenerator {
+                channel: context.choice(data, o(0), &name("channel"),
+                    ReceiveChannel::try_from, ReceiveChannel::Omni)?,
+                voice: context.ranged::<VoiceNumber>(data, o(1), &name("voice"))?,
+                detune: context.ranged::<Detune>(data, o(2), &name("detune"))?,
+                output_level: context.ranged::<Level>(data, o(3), &name("output_level"))?,
+                output_assign: context.choice(data, o(4), &name("output_assign"),
+                    OutputAssign::try_from, OutputAssign::Both)?,
+                note_limit_low: context.ranged::<Note>(data, o(5), &name("note_limit_low"))?,
+                note_limit_high: context.ranged::<Note>(data, o(6), &name("note_limit_high"))?,
+                note_shift: context.ranged::<Transpose>(data, o(7), &name("note_shift"))?,
+                key_assign_group: context.switch(data, o(8), &name("key_assign_group"))?,
+            };
+        }
+
+        let name_offset = PERFORMANCE_SIZE - PERFORMANCE_NAME_LENGTH;
+        Ok(Self {
+            tone_generators,
+            name: context.name(data, name_offset, PERFORMANCE_NAME_LENGTH)?,
+        })
+    }
+
+    /// Gets the voices of the tone generators from `cartridge`.
+    pub fn voices<'a>(&self, cartridge: &'a Cartridge) -> Vec<Option<&'a Voice>> {
+        self.tone_generators.iter().map(|tg| tg.voice_in(cartridge)).collect()
+    }
+}
+
+impl Default for Performance {
+    fn default() -> Self {
+        Performance::new()
+    }
+}
+
+impl SystemExclusiveData for Performance {
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let tgs = &self.tone_generators;
+        let mut data: Vec<u8> = Vec::new();
+        data.extend(tgs.iter().map(|tg| u8::from(tg.channel)));
+        data.extend(tgs.iter().map(|tg| tg.voice.encode()));
+        data.extend(tgs.iter().map(|tg| tg.detune.encode()));
+        data.extend(tgs.iter().map(|tg| tg.output_level.encode()));
+        data.extend(tgs.iter().map(|tg| u8::from(tg.output_assign)));
+        data.extend(tgs.iter().map(|tg| tg.note_limit_low.encode()));
+        data.extend(tgs.iter().map(|tg| tg.note_limit_high.encode()));
+        data.extend(tgs.iter().map(|tg| tg.note_shift.encode()));
+        data.extend(tgs.iter().map(|tg| if tg.key_assign_group { 1 } else { 0 }));
+
+        data.extend(name_bytes(&self.name, PERFORMANCE_NAME_LENGTH));
+
+        assert_eq!(data.len(), PERFORMANCE_SIZE);
+        data
+    }
+
+    fn data_size() -> usize { PERFORMANCE_SIZE }
+}
+
+impl fmt::Display for Performance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+        for (i, tg) in self.tone_generators.iter().enumerate() {
+            writeln!(f, "TG{}: {}", i + 1, tg)?;
+        }
+        Ok(())
+    }
+}
+
+/// PMEM bank of 32 TX802 performances.
+#[derive(Debug, Clone)]
+pub struct PerformanceBank {
+    pub performances: Vec<Performance>,
+}
+
+impl PerformanceBank {
+    /// Makes a performance bank from SysEx bytes, handling out-of-range
+    /// values according to the context policy.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Self, ParseError> {
+        check_length(data, PERFORMANCE_BANK_SIZE, "TX802 performance bank")?;
+
+        let mut performances = Vec::<Performance>::new();
+        for (i, performance_data) in data.chunks(PERFORMANCE_SIZE).enumerate() {
+            let offset = i * PERFORMANCE_SIZE;
+            let performance = context.nested(&format!("performances[{}]", i), offset,
+                |c| Performance::parse_with(performance_data, c))?;
+            performances.push(performance);
+        }
+        Ok(PerformanceBank { performances })
+    }
+}
+
+impl Default for PerformanceBank {
+    fn default() -> Self {
+        PerformanceBank {
+            performances: vec![Default::default(); PERFORMANCE_COUNT],
+        }
+    }
+}
+
+impl SystemExclusiveData for PerformanceBank {
+    fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for performance in &self.performances {
+            data.extend(performance.to_bytes());
+        }
+        data
+    }
+
+    fn data_size() -> usize { PERFORMANCE_BANK_SIZE }
+}
+
+/// A complete multi-timbral setup: a performance and the cartridge
+/// holding the voices it refers to.
+#[derive(Debug, Clone)]
+pub struct Setup {
+    pub performance: Performance,
+    pub cartridge: Cartridge,
+}
+
+impl Setup {
+    /// Makes the bulk dumps to archive this setup: first the voices,
+    /// then the performance.
+    pub fn to_messages(&self, channel: MidiChannel) -> Vec<Message> {
+        vec![
+            Message::cartridge(channel, self.cartridge.clone()),
+            Message::tx802_performance(channel, self.performance.clone()),
+        ]
+    }
+
+    /// Recalls a setup from bulk dumps, using the first cartridge
+    /// and the first TX802 performance found.
+    pub fn from_messages(messages: &[Message]) -> Option<Self> {
+        let cartridge = messages.iter().find_map(|m| match &m.payload {
+            Payload::Cartridge(cartridge) => Some(cartridge.clone()),
+            _ => None,
+        })?;
+        let performance = messages.iter().find_map(|m| match &m.payload {
+            Payload::Tx802Performance(performance) => Some((**performance).clone()),
+            _ => None,
+        })?;
+        Some(Setup { performance, cartridge })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_performance() -> Performance {
+        let mut performance = Performance::new();
+        performance.name = format!("{:<20}", "Layered Pad");
+        performance.tone_generators[1] = ToneGenerator {
+            channel: ReceiveChannel::Omni,
+            voice: VoiceNumber::new(32),
+            detune: Detune::new(-3),
+            note_limit_low: Note::new(60),
+            ..ToneGenerator::new()
+        };
+        performance
+    }
+
+    #[test]
+    fn test_performance_round_trip() {
+        let performance = make_performance();
+        let data = performance.to_bytes();
+        assert_eq!(data.len(), PERFORMANCE_SIZE);
+        assert_eq!(data[1], 16);  // TG2 channel is omni
+        assert_eq!(data[9], 31);  // TG2 voice
+        assert_eq!(data[17], 4);  // TG2 detune
+
+        let parsed = Performance::parse(&data).unwrap();
+        assert_eq!(parsed.tone_generators, performance.tone_generators);
+        assert_eq!(parsed.name.trim_end(), "Layered Pad");
+    }
+
+    #[test]
+    fn test_name_bytes() {
+        let performance = Performance { name: "Très longue « nappe » 2".to_string(), ..Performance::new() };
+        let data = performance.to_bytes();
+        assert_eq!(data.len(), PERFORMANCE_SIZE);
+        assert!(data.iter().all(|b| b.is_ascii()));
+        assert_eq!(Performance::parse(&data).unwrap().name, "Tr s longue   nappe ");
+
+        let mut data = make_performance().to_bytes();
+        data[PERFORMANCE_SIZE - 1] = 0xc3;
+        let mut context = ParseContext::lenient();
+        Performance::parse_with(&data, &mut context).unwrap();
+        assert_eq!(context.repairs[0].parameter, "name[19]");
+    }
+
+    #[test]
+    fn test_voice_reference() {
+        let mut cartridge = Cartridge::default();
+        cartridge.voices[31].alg = crate::dx7::Algorithm::new(5);
+        let performance = make_performance();
+        let voices = performance.voices(&cartridge);
+        assert_eq!(voices[1].expect("voice 32").alg.value(), 5);
+
+        let mut tg = ToneGenerator::new();
+        tg.voice = VoiceNumber::new(33);
+        assert!(tg.voice_in(&cartridge).is_none());
+    }
+
+    #[test]
+    fn test_setup_messages() {
+        let setup = Setup { performance: make_performance(), cartridge: Cartridge::default() };
+        let data: Vec<u8> = setup.to_messages(MidiChannel::new(1)).iter()
+            .flat_map(|m| m.to_bytes())
+            .collect();
+        let messages: Vec<Message> = crate::dx7::sysex::scan(&data).into_iter()
+            .filter_map(|entry| match entry {
+                crate::dx7::sysex::Entry::Message { message, .. } => Some(message),
+                _ => None,
+            })
+            .collect();
+        let recalled = Setup::from_messages(&messages).expect("complete setup");
+        assert_eq!(recalled.performance, setup.performance);
+    }
+}
@@ -1,14 +1,12 @@
 use std::fmt;
 
 use bit::BitIndex;
-use dbg_hex::dbg_hex;
 use rand::prelude::IndexedRandom;
 
 use syxpack::{
     ParseError,
     Ranged,
     Encoding,
-    parse_or_default,
     SystemExclusiveData,
 };
 
@@ -17,18 +15,25 @@ use crate::dx7::{
     Depth,
     Transpose,
     Level,
+    check_length,
+    name_bytes,
 };
 
 use crate::dx7::operator::Operator;
 use crate::dx7::lfo::Lfo;
 use crate::dx7::envelope::Envelope;
+use crate::dx7::policy::ParseContext;
 
 pub const OPERATOR_COUNT: usize = 6;
 pub const VOICE_PACKED_SIZE: usize = 128;
 pub const VOICE_SIZE: usize = 155;
+pub const OPERATOR_SIZE: usize = 21;
+pub const VOICE_NAME_LENGTH: usize = 10;
 
 /// Voice name.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct VoiceName {
     value: String,
 }
@@ -42,6 +47,12 @@ impl VoiceName {
         VoiceName { value: name.clone() }
     }
 
+    /// Returns `true` if `name` fits in the voice data: at most
+    /// `VOICE_NAME_LENGTH` ASCII characters.
+    pub fn is_valid(name: &str) -> bool {
+        name.len() <= VOICE_NAME_LENGTH && name.is_ascii()
+    }
+
     pub fn random() -> Self {
         // Five two-character syllables
         VoiceName { value: Self::make_random_phrase(5) }
@@ -51,11 +62,11 @@ impl VoiceName {
     // The result is not linguistically correct.
     fn make_random_syllable() -> String {
         // Japanese vowels and consonants. See https://www.lingvozone.com/Japanese.
-        let consonants = vec![
+        let consonants = [
             'k', 's', 't', 'n', 'h', 'm', 'y', 'r',
             'w', 'g', 'z', 'd', 'b', 'p'
         ];
-        let vowels = vec!['a', 'i', 'u', 'e', 'o'];
+        let vowels = ['a', 'i', 'u', 'e', 'o'];
         let mut rng = rand::rng();
         let consonant = consonants.choose(&mut rng).unwrap();
         let vowel = vowels.choose(&mut rng).unwrap();
@@ -85,13 +96,18 @@ impl fmt::Display for VoiceName {
 
 /// A DX7 voice.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Voice {
-    pub operators: [Operator; OPERATOR_COUNT],
+    pub operators: [Operator; OPERATOR_COUNT],  // OP1 first
+    #[cfg_attr(feature = "serde", serde(rename = "pitch_envelope"))]
     pub peg: Envelope,  // pitch env
+    #[cfg_attr(feature = "serde", serde(rename = "algorithm"))]
     pub alg: Algorithm,  // 1...32
     pub feedback: Depth,
+    #[cfg_attr(feature = "serde", serde(rename = "oscillator_sync"))]
     pub osc_sync: bool,
     pub lfo: Lfo,
+    #[cfg_attr(feature = "serde", serde(rename = "pitch_mod_sensitivity"))]
     pub pitch_mod_sens: Depth,  // pitch mode sensitivity 0 ~7 (for all operators)
     pub transpose: Transpose,  // number of octaves to transpose (-2...+2) (12 = C2 (value is 0~48 in SysEx))
     pub name: VoiceName,
@@ -133,7 +149,7 @@ impl Voice {
         // so just take each chunk and pack it.
         for _i in 0..6 {
             let op_data = &data[offset .. offset + 21];
-            let op_data_packed = Operator::pack(&op_data);
+            let op_data_packed = Operator::pack(op_data);
             result.extend(op_data_packed);
             offset += 21;
         }
@@ -176,6 +192,26 @@ impl Voice {
         result
     }
 
+    /// Maps an offset in the unpacked voice data to the offset
+    /// of the byte that holds the same parameter in packed voice data.
+    pub fn packed_offset(offset: usize) -> usize {
+        // Packed offsets of the unpacked operator parameters
+        const OPERATOR_OFFSETS: [usize; OPERATOR_SIZE] = [
+            0, 1, 2, 3, 4, 5, 6, 7,  // EG
+            8, 9, 10, 11, 11,  // KLS
+            12, 13, 13, 14, 15, 15, 16, 12,
+        ];
+
+        match offset {
+            0..126 => (offset / OPERATOR_SIZE) * 17 + OPERATOR_OFFSETS[offset % OPERATOR_SIZE],
+            126..=134 => offset - 24,  // PEG and algorithm
+            135 | 136 => 111,  // feedback and osc sync
+            137..=140 => offset - 25,  // LFO speed, delay, PMD, AMD
+            141..=143 => 116,  // LFO sync, waveform and pitch mod sens
+            _ => offset - 27,  // transpose and name
+        }
+    }
+
     /// Unpack voice data from a cartridge.
     /// Returns a vector to use for normal parsing.
     pub fn unpack(data: &[u8]) -> Vec<u8> {
@@ -232,69 +268,55 @@ impl Default for Voice {
     }
 }
 
-impl SystemExclusiveData for Voice {
-    fn parse(data: &[u8]) -> Result<Voice, ParseError> {
-        //eprintln!("Voice data length = {}", data.len());
-        //dbg_hex!(data);
+impl Voice {
+    /// Makes a voice from SysEx bytes, handling out-of-range values
+    /// according to the context policy.
+    pub fn parse_with(data: &[u8], context: &mut ParseContext) -> Result<Voice, ParseError> {
+        check_length(data, VOICE_SIZE, "voice")?;
 
         // Note that the operator data is in reverse order:
         // OP6 is first, OP1 is last.
+        let mut operators = [Operator::new(); OPERATOR_COUNT];
+        for (i, operator) in operators.iter_mut().rev().enumerate() {
+            let offset = i * OPERATOR_SIZE;
+            let number = OPERATOR_COUNT - i;
+            context.operator = Some(number);
+            let result = context.nested(&format!("op{}", number), offset,
+                |c| Operator::parse_with(&data[offset..offset + OPERATOR_SIZE], c));
+            context.operator = None;
+            *operator = result?;
+        }
 
-        //dbg_hex!(&data[0..21]);
-        let op6 = Operator::parse(&data[0..21])?;
-
-        //dbg!(&data[21..42]);
-        let op5 = Operator::parse(&data[21..42])?;
-
-        //dbg!(&data[42..63]);
-        let op4 = Operator::parse(&data[42..63])?;
-
-        //dbg!(&data[63..84]);
-        let op3 = Operator::parse(&data[63..84])?;
-
-        //dbg!(&data[84..105]);
-        let op2 = Operator::parse(&data[84..105])?;
-
-        //dbg!(&data[105..126]);
-        let op1 = Operator::parse(&data[105..126])?;
-
-        //dbg!(&data[126..134]);
-        let peg = Envelope::parse(&data[126..134])?;
-
-        //dbg_hex!(data[134]);
-        let alg = parse_or_default::<Algorithm>(data[134]);        
-
-        //dbg_hex!(data[135]);
-        let feedback = parse_or_default::<Depth>(data[135]);
-
-        //dbg_hex!(data[144]);
-        let transpose = parse_or_default::<Transpose>(data[144]);
+        let peg = context.nested("peg", 126,
+            |c| Envelope::parse_with(&data[126..134], c))?;
+        let alg = context.ranged::<Algorithm>(data, 134, "alg")?;
+        let feedback = context.ranged::<Depth>(data, 135, "feedback")?;
+        let osc_sync = context.switch(data, 136, "osc_sync")?;
+        let lfo = context.nested("lfo", 137,
+            |c| Lfo::parse_with(&data[137..143], c))?;
+        let pitch_mod_sens = context.ranged::<Depth>(data, 143, "pitch_mod_sens")?;
+        let transpose = context.ranged::<Transpose>(data, 144, "transpose")?;
 
-        //dbg_hex!(&data[145..155]);
-        let name = VoiceName::from_string(
-            String::from_utf8(data[145..155].to_vec())
-                .unwrap()
-        );
+        let name = VoiceName::from_string(context.name(data, 145, VOICE_NAME_LENGTH)?);
 
         Ok(Voice {
-            operators: [
-                op1,
-                op2,
-                op3,
-                op4,
-                op5,
-                op6,
-            ],
+            operators,
             peg,
             alg,
             feedback,
-            osc_sync: data[136] == 1,
-            lfo: Lfo::parse(&data[137..143])?,
-            pitch_mod_sens: parse_or_default::<Depth>(data[143]),
+            osc_sync,
+            lfo,
+            pitch_mod_sens,
             transpose,
             name,
         })
     }
+}
+
+impl SystemExclusiveData for Voice {
+    fn parse(data: &[u8]) -> Result<Voice, ParseError> {
+        Self::parse_with(data, &mut ParseContext::lenient())
+    }
 
     fn to_bytes(&self) -> Vec<u8> {
         let mut data: Vec<u8> = Vec::new();
@@ -312,8 +334,7 @@ impl SystemExclusiveData for Voice {
         data.push(self.pitch_mod_sens.encode());
         data.push(self.transpose.encode());
 
-        let padded_name = format!("{:<10}", self.name.value());
-        data.extend(padded_name.into_bytes());
+        data.extend(name_bytes(&self.name.value(), VOICE_NAME_LENGTH));
 
         assert_eq!(data.len(), VOICE_SIZE);
 
@@ -360,6 +381,7 @@ mod tests {
     use crate::dx7::operator::*;
     use crate::dx7::lfo::*;
     use crate::dx7::envelope::*;
+    use crate::dx7::policy::*;
 
     /// Makes a new voice based on the "BRASS1" settings in the DX7 manual.
     pub fn make_brass1() -> Voice {
@@ -400,7 +422,7 @@ mod tests {
         };
 
         let op4 = Operator {
-            eg: op5.eg.clone(),
+            eg: op5.eg,
             kbd_level_scaling,
             output_level: Level::new(99),
             ..op
@@ -498,4 +520,49 @@ mod tests {
 
         assert_eq!(voice_data, brass1_data);
     }
+
+    #[test]
+    fn test_parse_short_data() {
+        let data = Voice::new().to_bytes();
+        assert!(Voice::parse(&data[..100]).is_err());
+    }
+
+    #[test]
+    fn test_parse_bad_curve() {
+        let mut data = make_brass1().to_bytes();
+        data[105 + 11] = 4;  // OP1 is last, left curve is at offset 11
+        assert_eq!(
+            Voice::parse_with(&data, &mut ParseContext::strict()).unwrap_err(),
+            ParseError::InvalidData(116,
+                "op1.kbd_level_scaling.left.curve: Bad scaling curve value, expected 0...3".to_string()));
+    }
+
+    #[test]
+    fn test_parse_lenient_repairs() {
+        let mut data = make_brass1().to_bytes();
+        data[63 + 1] = 120;  // OP3 EG rate 2
+        data[134] = 40;  // algorithm
+        let mut context = ParseContext::lenient();
+        let voice = Voice::parse_with(&data, &mut context).expect("lenient parse");
+        assert_eq!(voice.operators[2].eg.rates[1].value(), 99);
+        assert_eq!(voice.alg.value(), Algorithm::DEFAULT);
+        assert_eq!(context.repairs, vec![
+            Repair {
+                voice: None,
+                operator: Some(3),
+                parameter: "op3.eg.rates[1]".to_string(),
+                raw: 120,
+                value: 99,
+            },
+            Repair {
+                voice: None,
+                operator: None,
+                parameter: "alg".to_string(),
+                raw: 40,
+                value: 32,
+            },
+        ]);
+
+        assert!(Voice::parse_with(&data, &mut ParseContext::strict()).is_err());
+    }
 }
@@ -0,0 +1,278 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{
+    self,
+    BufWriter,
+    Write,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use syxpack::Ranged;
+
+use crate::dx7::{
+    Note,
+    Velocity,
+};
+use crate::dx7::cartridge::Cartridge;
+use crate::dx7::render::Renderer;
+use crate::dx7::voice::Voice;
+
+/// Sample format of a WAV file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SampleFormat {
+    Pcm16,
+    Pcm24,
+    Float32,
+}
+
+impl SampleFormat {
+    /// Gets the number of bits in one sample.
+    pub fn bits_per_sample(&self) -> u16 {
+        match self {
+            SampleFormat::Pcm16 => 16,
+            SampleFormat::Pcm24 => 24,
+            SampleFormat::Float32 => 32,
+        }
+    }
+
+    // Gets the WAVE format tag: 1 = PCM, 3 = IEEE float.
+    fn format_tag(&self) -> u16 {
+        match self {
+            SampleFormat::Float32 => 3,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            SampleFormat::Pcm16 => "16-bit PCM",
+            SampleFormat::Pcm24 => "24-bit PCM",
+            SampleFormat::Float32 => "32-bit float",
+        })
+    }
+}
+
+/// Writes mono `samples` to `writer` as a WAV file.
+/// Fails with `InvalidInput` if there are too many samples
+/// for the 32-bit sizes of a WAV file.
+pub fn write_wav<W: Write>(mut writer: W, samples: &[f32], sample_rate: u32, format: SampleFormat) -> io::Result<()> {
+    let bytes_per_sample = format.bits_per_sample() as u32 / 8;
+
+    // Non-PCM formats have an extended format chunk and a fact chunk.
+    let is_float = format == SampleFormat::Float32;
+    let format_size: u32 = if is_float { 18 } else { 16 };
+    let fact_size: u32 = if is_float { 12 } else { 0 };
+
+    // RIFF chunks are word aligned, so an odd-sized data chunk
+    // is followed by a pad byte that is counted in the RIFF size.
+    let too_long = || io::Error::new(io::ErrorKind::InvalidInput,
+        format!("{} samples do not fit in a WAV file", samples.len()));
+    let data_size = u32::try_from(samples.len()).ok()
+        .and_then(|count| count.checked_mul(bytes_per_sample))
+        .ok_or_else(too_long)?;
+    let pad_size = data_size % 2;
+    let riff_size = (4 + 8 + format_size + fact_size + 8 + pad_size).checked_add(data_size)
+        .ok_or_else(too_long)?;
+    let byte_rate = sample_rate.checked_mul(bytes_per_sample)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+            format!("sample rate {} is too high for a WAV file", sample_rate)))?;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&riff_size.to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&format_size.to_le_bytes())?;
+    writer.write_all(&format.format_tag().to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;  // channels
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&byte_rate.to_le_bytes())?;  // bytes per second
+    writer.write_all(&(bytes_per_sample as u16).to_le_bytes())?;  // block align
+    writer.write_all(&format.bits_per_sample().to_le_bytes())?;
+    if is_float {
+        writer.write_all(&0u16.to_le_bytes())?;  // extension size
+        writer.write_all(b"fact")?;
+        writer.write_all(&4u32.to_le_bytes())?;
+        writer.write_all(&(samples.len() as u32).to_le_bytes())?;
+    }
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        let sample = sample.clamp(-1.0, 1.0);
+        match format {
+            SampleFormat::Pcm16 => {
+                let value = (sample * 32767.0).round() as i16;
+                writer.write_all(&value.to_le_bytes())?;
+            },
+            SampleFormat::Pcm24 => {
+                let value = (sample * 8388607.0).round() as i32;
+                writer.write_all(&value.to_le_bytes()[..3])?;
+            },
+            SampleFormat::Float32 => {
+                writer.write_all(&sample.to_le_bytes())?;
+            },
+        }
+    }
+    if pad_size != 0 {
+        writer.write_all(&[0])?;
+    }
+    writer.flush()
+}
+
+/// Settings for rendering voices to audio files.
+#[derive(Debug, Clone)]
+pub struct Audition {
+    /// Notes played one after the other for each voice.
+    pub notes: Vec<Note>,
+    pub velocity: Velocity,
+
+    /// How long each note is held, in seconds.
+    pub duration: f64,
+
+    /// Silence after each note, in seconds.
+    pub gap: f64,
+
+    pub format: SampleFormat,
+}
+
+impl Audition {
+    /// Makes new audition settings: middle C held for one second
+    /// in 16-bit PCM.
+    pub fn new() -> Self {
+        Self {
+            notes: vec![Note::new(60)],
+            velocity: Velocity::default(),
+            duration: 1.0,
+            gap: 0.5,
+            format: SampleFormat::Pcm16,
+        }
+    }
+
+    /// Renders all the notes of `voice`, each followed by the gap.
+    pub fn render_voice(&self, renderer: &Renderer, voice: &Voice) -> Vec<f32> {
+        let gap = (self.gap.max(0.0) * renderer.sample_rate as f64) as usize;
+        let mut samples = Vec::new();
+        for note in &self.notes {
+            samples.extend(renderer.render(voice, *note, self.velocity, self.duration));
+            samples.extend(std::iter::repeat_n(0.0, gap));
+        }
+        samples
+    }
+
+    /// Renders every voice of `cartridge` into one audition reel.
+    pub fn render_reel(&self, renderer: &Renderer, cartridge: &Cartridge) -> Vec<f32> {
+        cartridge.voices.iter()
+            .flat_map(|voice| self.render_voice(renderer, voice))
+            .collect()
+    }
+
+    /// Writes `voice` to `writer` as a WAV file.
+    pub fn write_voice<W: Write>(&self, writer: W, renderer: &Renderer, voice: &Voice) -> io::Result<()> {
+        write_wav(writer, &self.render_voice(renderer, voice), renderer.sample_rate, self.format)
+    }
+
+    /// Writes the audition reel of `cartridge` to `writer` as a WAV file.
+    pub fn write_reel<W: Write>(&self, writer: W, renderer: &Renderer, cartridge: &Cartridge) -> io::Result<()> {
+        write_wav(writer, &self.render_reel(renderer, cartridge), renderer.sample_rate, self.format)
+    }
+
+    /// Writes each voice of `cartridge` into a separate WAV file
+    /// in `directory`. Returns the paths of the files written.
+    pub fn write_cartridge(&self, directory: &Path, renderer: &Renderer, cartridge: &Cartridge) -> io::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for (index, voice) in cartridge.voices.iter().enumerate() {
+            let path = directory.join(file_name(index + 1, voice));
+            let file = BufWriter::new(File::create(&path)?);
+            self.write_voice(file, renderer, voice)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+impl Default for Audition {
+    fn default() -> Self {
+        Audition::new()
+    }
+}
+
+/// Makes a WAV file name for the voice `number` in a cartridge,
+/// like "01 BRASS 1.wav". Characters that are not safe in file names
+/// are replaced with underscores.
+pub fn file_name(number: usize, voice: &Voice) -> String {
+    let name: String = voice.name.value().trim().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || " -.+#()".contains(c) { c } else { '_' })
+        .collect();
+    format!("{:02} {}.wav", number, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dx7::voice::VoiceName;
+
+    #[test]
+    fn test_write_wav_pcm16() {
+        let mut data = Vec::new();
+        write_wav(&mut data, &[0.0, 1.0, -1.0], 44100, SampleFormat::Pcm16).unwrap();
+        assert_eq!(data.len(), 44 + 6);
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes([data[4], data[5], data[6], data[7]]), 42);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(&data[44..], &[0x00, 0x00, 0xff, 0x7f, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn test_write_wav_other_formats() {
+        let mut data = Vec::new();
+        write_wav(&mut data, &[0.5], 48000, SampleFormat::Pcm24).unwrap();
+        assert_eq!(&data[44..], &[0x00, 0x00, 0x40, 0x00]);  // with the pad byte
+
+        let mut data = Vec::new();
+        write_wav(&mut data, &[0.5, 0.25], 48000, SampleFormat::Float32).unwrap();
+        assert_eq!(data.len(), 58 + 8);
+        assert_eq!(u16::from_le_bytes([data[20], data[21]]), 3);
+        assert_eq!(&data[38..42], b"fact");
+        assert_eq!(&data[58..62], &0.5f32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_write_wav_pad_byte() {
+        // Three 24-bit samples make an odd-sized data chunk.
+        let mut data = Vec::new();
+        write_wav(&mut data, &[0.0; 3], 44100, SampleFormat::Pcm24).unwrap();
+        assert_eq!(data.len(), 44 + 9 + 1);
+        assert_eq!(u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize, data.len() - 8);
+        assert_eq!(u32::from_le_bytes([data[40], data[41], data[42], data[43]]), 9);
+        assert_eq!(data[data.len() - 1], 0);
+
+        // An even-sized data chunk has no pad byte.
+        let mut data = Vec::new();
+        write_wav(&mut data, &[0.0; 2], 44100, SampleFormat::Pcm24).unwrap();
+        assert_eq!(data.len(), 44 + 6);
+
+        let error = write_wav(Vec::new(), &[], u32::MAX, SampleFormat::Pcm16).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_reel() {
+        let renderer = Renderer::new(8000);
+        let audition = Audition { duration: 0.1, gap: 0.25, ..Audition::new() };
+        let mut cartridge = Cartridge::default();
+        cartridge.voices.truncate(2);
+        let reel = audition.render_reel(&renderer, &cartridge);
+
+        // The voices are silent, so each one is just the note and the gap.
+        assert_eq!(reel.len(), 2 * (800 + 2000));
+
+        let voice = Voice { name: VoiceName::new("E.PIANO/1 "), ..Voice::new() };
+        assert_eq!(file_name(3, &voice), "03 E.PIANO_1.wav");
+    }
+}